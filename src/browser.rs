@@ -1,5 +1,6 @@
 use anyhow::{anyhow, Result};
 use js_sys::ArrayBuffer;
+use serde::Serialize;
 use std::future::Future;
 use wasm_bindgen::closure::{Closure, WasmClosure, WasmClosureFnOnce};
 use wasm_bindgen::{JsCast, JsValue};
@@ -90,6 +91,15 @@ pub async fn fetch_array_buffer(resource: &str) -> Result<ArrayBuffer> {
         .map_err(|err| anyhow!("Error converting raw JSValue to ArrayBuffer {:#?}", err))
 }
 
+pub fn to_pretty_json(value: &impl Serialize) -> Result<String> {
+    let value = JsValue::from_serde(value)
+        .map_err(|err| anyhow!("Could not convert value to JavaScript {:#?}", err))?;
+    js_sys::JSON::stringify_with_replacer_and_space(&value, &JsValue::NULL, &JsValue::from(2))
+        .map_err(|err| anyhow!("Could not stringify value {:#?}", err))?
+        .as_string()
+        .ok_or_else(|| anyhow!("Stringified value was not a string"))
+}
+
 pub fn new_image() -> Result<HtmlImageElement> {
     HtmlImageElement::new().map_err(|err| anyhow!("Could not create HtmlImageElement: {:#?}", err))
 }
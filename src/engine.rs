@@ -5,15 +5,106 @@ use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use futures::channel::mpsc::{unbounded, UnboundedReceiver};
 use futures::channel::oneshot::channel;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::sync::Mutex;
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::{JsCast, JsValue};
 use web_sys::{AudioBuffer, AudioContext, CanvasRenderingContext2d, HtmlImageElement};
 
+mod assets;
+mod collision;
+mod ecs;
+mod fixed;
+mod grid;
+mod parallax;
+mod state_hash;
+
+pub use assets::{AssetError, AssetKind, AssetManifest, Assets, LoadError, LoadProgress};
+pub use collision::{collide, Side};
+pub use ecs::{Collider, ColliderKind, Entity, Sprite, World};
+pub use fixed::Fixed;
+pub use parallax::{Parallax, ParallaxDefinition};
+pub use state_hash::state_hash;
+
+// What a texture is drawn from: the browser's decoded image for the canvas,
+// or RGBA rows a backend without a browser decodes once and keeps.
+enum Pixels {
+    Image(HtmlImageElement),
+    Rgba(Rc<[u8]>),
+}
+
+struct TextureData {
+    pixels: Pixels,
+    width: i16,
+    height: i16,
+}
+
+#[derive(Clone)]
+pub struct Texture {
+    data: Rc<TextureData>,
+}
+impl Texture {
+    pub fn new(element: HtmlImageElement) -> Self {
+        Texture {
+            data: Rc::new(TextureData {
+                width: element.width() as i16,
+                height: element.height() as i16,
+                pixels: Pixels::Image(element),
+            }),
+        }
+    }
+    // Nothing outside the tests decodes images yet.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn from_rgba(width: i16, height: i16, pixels: Rc<[u8]>) -> Result<Self> {
+        let expected = width.max(0) as usize * height.max(0) as usize * 4;
+        if pixels.len() != expected {
+            return Err(anyhow!(
+                "A {}x{} texture needs {} RGBA bytes, got {}",
+                width,
+                height,
+                expected,
+                pixels.len()
+            ));
+        }
+        Ok(Texture {
+            data: Rc::new(TextureData {
+                pixels: Pixels::Rgba(pixels),
+                width,
+                height,
+            }),
+        })
+    }
+    // A transparent texture of the given size, for tests that only need its
+    // dimensions.
+    #[cfg(test)]
+    pub fn headless(width: i16, height: i16) -> Self {
+        let size = width.max(0) as usize * height.max(0) as usize * 4;
+        Texture::from_rgba(width, height, vec![0; size].into()).unwrap()
+    }
+    pub fn width(&self) -> i16 {
+        self.data.width
+    }
+    pub fn height(&self) -> i16 {
+        self.data.height
+    }
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn rgba(&self) -> Option<&[u8]> {
+        match &self.data.pixels {
+            Pixels::Rgba(pixels) => Some(pixels),
+            Pixels::Image(_) => None,
+        }
+    }
+    fn element(&self) -> Result<&HtmlImageElement> {
+        match &self.data.pixels {
+            Pixels::Image(element) => Ok(element),
+            Pixels::Rgba(_) => Err(anyhow!("The canvas can't draw a decoded RGBA texture")),
+        }
+    }
+}
+
 pub async fn load_image(source: &str) -> Result<HtmlImageElement> {
     let image = browser::new_image()?;
 
@@ -67,9 +158,7 @@ impl GameLoop {
             accumulated_delta: 0.0,
         };
 
-        let renderer = Renderer {
-            context: browser::context()?,
-        };
+        let renderer = Renderer::new(browser::context()?);
 
         let f: SharedLoopClosure = Rc::new(RefCell::new(None));
         let g = f.clone();
@@ -80,11 +169,13 @@ impl GameLoop {
             game_loop.accumulated_delta += (perf - game_loop.last_frame) as f32;
             while game_loop.accumulated_delta > FRAME_SIZE {
                 game.update(&keystate);
+                keystate.end_update();
                 game_loop.accumulated_delta -= FRAME_SIZE;
             }
             game_loop.last_frame = perf;
 
             game.draw(&renderer);
+            renderer.report_errors();
 
             browser::request_animation_frame(f.borrow().as_ref().unwrap());
         }));
@@ -98,146 +189,400 @@ impl GameLoop {
     }
 }
 
+const PLACEHOLDER_COLOR: &str = "#FF00FF";
+
+// Maps world space onto the canvas: `position` is the world point at the
+// canvas's top-left corner and `zoom` how many screen pixels a world unit
+// covers.
+#[derive(Clone, Copy)]
+pub struct Camera {
+    pub position: Point,
+    pub zoom: Fixed,
+}
+impl Default for Camera {
+    fn default() -> Self {
+        Camera::new(Point::default())
+    }
+}
+impl Camera {
+    pub fn new(position: Point) -> Self {
+        Camera {
+            position,
+            zoom: Fixed::from_int(1),
+        }
+    }
+    // Moves the camera so `target` lands on `screen_position`.
+    pub fn follow(&mut self, target: Point, screen_position: Point) {
+        self.position = Point {
+            x: target.x - screen_position.x / self.zoom,
+            y: target.y - screen_position.y / self.zoom,
+        };
+    }
+    pub fn left(&self) -> Fixed {
+        self.position.x
+    }
+    // How much of the world a viewport `width` screen pixels wide shows.
+    pub fn world_width(&self, width: Fixed) -> Fixed {
+        width / self.zoom
+    }
+    pub fn to_world(self, point: Point) -> Point {
+        Point {
+            x: point.x / self.zoom + self.position.x,
+            y: point.y / self.zoom + self.position.y,
+        }
+    }
+    pub fn to_screen(self, rect: &Rect) -> Rect {
+        Rect::new_from_x_y(
+            (rect.x() - self.position.x) * self.zoom,
+            (rect.y() - self.position.y) * self.zoom,
+            rect.width * self.zoom,
+            rect.height * self.zoom,
+        )
+    }
+}
+
 pub struct Renderer {
     context: CanvasRenderingContext2d,
+    camera: std::cell::Cell<Camera>,
+    errors: RefCell<Vec<String>>,
+    reported: RefCell<Vec<String>>,
 }
 
 impl Renderer {
+    fn new(context: CanvasRenderingContext2d) -> Self {
+        Renderer {
+            context,
+            camera: std::cell::Cell::new(Camera::default()),
+            errors: RefCell::new(Vec::new()),
+            reported: RefCell::new(Vec::new()),
+        }
+    }
+
+    // Everything drawn inside `draw` is in world coordinates seen through
+    // `camera`; everything outside it is drawn straight to the screen.
+    pub fn with_camera(&self, camera: &Camera, draw: impl FnOnce(&Renderer)) {
+        let previous = self.camera.replace(*camera);
+        draw(self);
+        self.camera.set(previous);
+    }
+
+    fn to_screen(&self, rect: &Rect) -> Rect {
+        self.camera.get().to_screen(rect)
+    }
+
+    pub fn viewport_width(&self) -> Fixed {
+        self.context
+            .canvas()
+            .map(|canvas| Fixed::from_int(canvas.width() as i32))
+            .unwrap_or_default()
+    }
+
+    pub fn collect(&self, result: Result<()>) {
+        if let Err(err) = result {
+            self.errors.borrow_mut().push(format!("{:#}", err));
+        }
+    }
+
+    // Logs the errors collected while drawing a frame, skipping frames that
+    // fail the same way as the last report so a broken sprite doesn't flood
+    // the console at 60fps.
+    fn report_errors(&self) {
+        let errors = self.errors.take();
+        if errors != *self.reported.borrow() {
+            if !errors.is_empty() {
+                log!("{} drawing error(s): {:#?}", errors.len(), errors);
+            }
+            *self.reported.borrow_mut() = errors;
+        }
+    }
+
     pub fn clear(&self, rect: &Rect) {
         self.context.clear_rect(
-            rect.x().into(),
-            rect.y().into(),
-            rect.width.into(),
-            rect.height.into(),
+            pixels(rect.x()),
+            pixels(rect.y()),
+            pixels(rect.width),
+            pixels(rect.height),
         );
     }
 
     pub fn draw_rect(&self, rect: &Rect) {
-        self.context.set_stroke_style(&JsValue::from_str("#FF0000"));
+        self.outline_rect(rect, "#FF0000");
+    }
+
+    pub fn outline_rect(&self, rect: &Rect, color: &str) {
+        let rect = self.to_screen(rect);
+        self.context.set_stroke_style(&JsValue::from_str(color));
         self.context.begin_path();
         self.context.rect(
-            rect.x().into(),
-            rect.y().into(),
-            rect.width.into(),
-            rect.height.into(),
+            pixels(rect.x()),
+            pixels(rect.y()),
+            pixels(rect.width),
+            pixels(rect.height),
         );
         self.context.stroke();
     }
 
-    pub fn draw_image(&self, image: &HtmlImageElement, frame: &Rect, destination: &Rect) {
+    pub fn fill_rect(&self, rect: &Rect, color: &str) {
+        let rect = self.to_screen(rect);
+        self.context.set_fill_style(&JsValue::from_str(color));
+        self.context.fill_rect(
+            pixels(rect.x()),
+            pixels(rect.y()),
+            pixels(rect.width),
+            pixels(rect.height),
+        );
+    }
+
+    pub fn draw_placeholder(&self, destination: &Rect) {
+        self.fill_rect(destination, PLACEHOLDER_COLOR);
+    }
+
+    pub fn draw_text(&self, text: &str, position: &Point, color: &str) -> Result<()> {
+        let position = self
+            .to_screen(&Rect::new(*position, Fixed::ZERO, Fixed::ZERO))
+            .position;
+        self.context.set_fill_style(&JsValue::from_str(color));
+        self.context.set_font("16px sans-serif");
+        self.context
+            .fill_text(text, pixels(position.x), pixels(position.y))
+            .map_err(|err| anyhow!("Error drawing text {:#?}", err))
+    }
+
+    pub fn draw_image(&self, texture: &Texture, frame: &Rect, destination: &Rect) -> Result<()> {
+        let destination = self.to_screen(destination);
         self.context
             .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
-                image,
-                frame.x().into(),
-                frame.y().into(),
-                frame.width.into(),
-                frame.height.into(),
-                destination.x().into(),
-                destination.y().into(),
-                destination.width.into(),
-                destination.height.into(),
+                texture.element()?,
+                pixels(frame.x()),
+                pixels(frame.y()),
+                pixels(frame.width),
+                pixels(frame.height),
+                pixels(destination.x()),
+                pixels(destination.y()),
+                pixels(destination.width),
+                pixels(destination.height),
             )
-            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+            .map_err(|err| anyhow!("Error drawing image {:#?}", err))
     }
 
-    pub fn draw_entire_image(&self, image: &HtmlImageElement, position: &Point) {
+    pub fn draw_entire_image(&self, texture: &Texture, position: &Point) -> Result<()> {
+        let destination = self.to_screen(&Rect::new(
+            *position,
+            texture.width().into(),
+            texture.height().into(),
+        ));
         self.context
-            .draw_image_with_html_image_element(image, position.x.into(), position.y.into())
-            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+            .draw_image_with_html_image_element_and_dw_and_dh(
+                texture.element()?,
+                pixels(destination.x()),
+                pixels(destination.y()),
+                pixels(destination.width),
+                pixels(destination.height),
+            )
+            .map_err(|err| anyhow!("Error drawing image {:#?}", err))
+    }
+
+    // Draws the whole image turned by `angle` radians about `pivot`.
+    pub fn draw_rotated_image(
+        &self,
+        texture: &Texture,
+        position: &Point,
+        pivot: &Point,
+        angle: f64,
+    ) -> Result<()> {
+        let element = texture.element()?;
+        let image = self.to_screen(&Rect::new(
+            *position,
+            texture.width().into(),
+            texture.height().into(),
+        ));
+        let pivot = self
+            .to_screen(&Rect::new(*pivot, Fixed::ZERO, Fixed::ZERO))
+            .position;
+        self.context.save();
+        let result = self
+            .context
+            .translate(pixels(pivot.x), pixels(pivot.y))
+            .and_then(|_| self.context.rotate(angle))
+            .and_then(|_| {
+                self.context
+                    .draw_image_with_html_image_element_and_dw_and_dh(
+                        element,
+                        pixels(image.x() - pivot.x),
+                        pixels(image.y() - pivot.y),
+                        pixels(image.width),
+                        pixels(image.height),
+                    )
+            });
+        self.context.restore();
+        result.map_err(|err| anyhow!("Error drawing rotated image {:#?}", err))
     }
 }
 
-#[derive(Default)]
+// Converts a world-space value to whole canvas pixels; this is the only place
+// fixed-point coordinates are rounded.
+fn pixels(value: Fixed) -> f64 {
+    value.to_pixels().into()
+}
+
+#[derive(Clone, Copy, Default, Hash, Deserialize, Serialize)]
 pub struct Rect {
+    #[serde(flatten)]
     pub position: Point,
-    pub width: i16,
-    pub height: i16,
+    pub width: Fixed,
+    pub height: Fixed,
 }
 impl Rect {
-    pub fn new(position: Point, width: i16, height: i16) -> Self {
+    pub fn new(position: Point, width: Fixed, height: Fixed) -> Self {
         Rect {
             position,
             width,
             height,
         }
     }
-    pub fn new_from_x_y(x: i16, y: i16, width: i16, height: i16) -> Self {
+    pub fn new_from_x_y(x: Fixed, y: Fixed, width: Fixed, height: Fixed) -> Self {
         Rect::new(Point { x, y }, width, height)
     }
+    pub const fn from_pixels(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Rect {
+            position: Point::from_pixels(x, y),
+            width: Fixed::from_int(width),
+            height: Fixed::from_int(height),
+        }
+    }
     pub fn intersects(&self, rect: &Rect) -> bool {
         self.x() < rect.right()
             && self.right() > rect.x()
             && self.y() < rect.bottom()
             && self.bottom() > rect.y()
     }
-    pub fn right(&self) -> i16 {
+    pub fn contains(&self, point: Point) -> bool {
+        point.x >= self.x()
+            && point.x < self.right()
+            && point.y >= self.y()
+            && point.y < self.bottom()
+    }
+    pub fn right(&self) -> Fixed {
         self.x() + self.width
     }
-    pub fn bottom(&self) -> i16 {
+    pub fn bottom(&self) -> Fixed {
         self.y() + self.height
     }
-    pub fn x(&self) -> i16 {
+    pub fn x(&self) -> Fixed {
         self.position.x
     }
-    pub fn set_x(&mut self, x: i16) {
+    pub fn set_x(&mut self, x: Fixed) {
         self.position.x = x
     }
-    pub fn y(&self) -> i16 {
+    pub fn y(&self) -> Fixed {
         self.position.y
     }
+    // The smallest rect around all of them, if there are any.
+    pub fn enclosing(rects: &[Rect]) -> Option<Rect> {
+        let left = rects.iter().map(Rect::x).min()?;
+        let top = rects.iter().map(Rect::y).min()?;
+        let right = rects.iter().map(Rect::right).max()?;
+        let bottom = rects.iter().map(Rect::bottom).max()?;
+        Some(Rect::new_from_x_y(left, top, right - left, bottom - top))
+    }
 }
 
-fn prepare_input() -> Result<UnboundedReceiver<KeyPress>> {
-    let (keydown_sender, keyevent_receiver) = unbounded();
+fn prepare_input() -> Result<UnboundedReceiver<InputEvent>> {
+    let (keydown_sender, input_receiver) = unbounded();
     let keydown_sender = Rc::new(RefCell::new(keydown_sender));
     let keyup_sender = Rc::clone(&keydown_sender);
+    let mousemove_sender = Rc::clone(&keydown_sender);
+    let mousedown_sender = Rc::clone(&keydown_sender);
+    let mouseup_sender = Rc::clone(&keydown_sender);
 
     let onkeydown = browser::closure_wrap(Box::new(move |keycode: web_sys::KeyboardEvent| {
         keydown_sender
             .borrow_mut()
-            .start_send(KeyPress::KeyDown(keycode));
+            .start_send(InputEvent::KeyDown(keycode));
     }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
 
     let onkeyup = browser::closure_wrap(Box::new(move |keycode: web_sys::KeyboardEvent| {
         keyup_sender
             .borrow_mut()
-            .start_send(KeyPress::KeyUp(keycode));
+            .start_send(InputEvent::KeyUp(keycode));
     }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
 
+    let onmousemove = browser::closure_wrap(Box::new(move |event: web_sys::MouseEvent| {
+        mousemove_sender
+            .borrow_mut()
+            .start_send(InputEvent::MouseMove(event));
+    }) as Box<dyn FnMut(web_sys::MouseEvent)>);
+
+    let onmousedown = browser::closure_wrap(Box::new(move |event: web_sys::MouseEvent| {
+        mousedown_sender
+            .borrow_mut()
+            .start_send(InputEvent::MouseDown(event));
+    }) as Box<dyn FnMut(web_sys::MouseEvent)>);
+
+    let onmouseup = browser::closure_wrap(Box::new(move |event: web_sys::MouseEvent| {
+        mouseup_sender
+            .borrow_mut()
+            .start_send(InputEvent::MouseUp(event));
+    }) as Box<dyn FnMut(web_sys::MouseEvent)>);
+
     browser::window()?.set_onkeydown(Some(onkeydown.as_ref().unchecked_ref()));
     browser::window()?.set_onkeyup(Some(onkeyup.as_ref().unchecked_ref()));
+    let canvas = browser::canvas()?;
+    canvas.set_onmousemove(Some(onmousemove.as_ref().unchecked_ref()));
+    canvas.set_onmousedown(Some(onmousedown.as_ref().unchecked_ref()));
+    canvas.set_onmouseup(Some(onmouseup.as_ref().unchecked_ref()));
     onkeydown.forget();
     onkeyup.forget();
+    onmousemove.forget();
+    onmousedown.forget();
+    onmouseup.forget();
 
-    Ok(keyevent_receiver)
+    Ok(input_receiver)
 }
 
-enum KeyPress {
+enum InputEvent {
     KeyUp(web_sys::KeyboardEvent),
     KeyDown(web_sys::KeyboardEvent),
+    MouseMove(web_sys::MouseEvent),
+    MouseDown(web_sys::MouseEvent),
+    MouseUp(web_sys::MouseEvent),
 }
 
-fn process_input(state: &mut KeyState, keyevent_receiver: &mut UnboundedReceiver<KeyPress>) {
+fn process_input(state: &mut KeyState, input_receiver: &mut UnboundedReceiver<InputEvent>) {
     loop {
-        match keyevent_receiver.try_next() {
+        match input_receiver.try_next() {
             Ok(None) => break,
             Err(_err) => break,
             Ok(Some(evt)) => match evt {
-                KeyPress::KeyUp(evt) => state.set_released(&evt.code()),
-                KeyPress::KeyDown(evt) => state.set_pressed(&evt.code(), evt),
+                InputEvent::KeyUp(evt) => state.set_released(&evt.code()),
+                InputEvent::KeyDown(evt) => state.set_pressed(&evt.code(), evt),
+                InputEvent::MouseMove(evt) => state.set_mouse(&evt, state.mouse_down),
+                InputEvent::MouseDown(evt) => state.set_mouse(&evt, true),
+                InputEvent::MouseUp(evt) => state.set_mouse(&evt, false),
             },
         }
     }
 }
 
+// Besides what is held right now, the state remembers what was held at the
+// end of the previous update, so one-shot actions can react to the moment a
+// key or button goes down instead of repeating every tick.
 pub struct KeyState {
     pressed_keys: HashMap<String, web_sys::KeyboardEvent>,
+    previous_keys: HashSet<String>,
+    mouse: Point,
+    mouse_down: bool,
+    previous_mouse_down: bool,
 }
 
 impl KeyState {
     fn new() -> Self {
         KeyState {
             pressed_keys: HashMap::new(),
+            previous_keys: HashSet::new(),
+            mouse: Point::default(),
+            mouse_down: false,
+            previous_mouse_down: false,
         }
     }
 
@@ -245,6 +590,19 @@ impl KeyState {
         self.pressed_keys.contains_key(code)
     }
 
+    pub fn was_just_pressed(&self, code: &str) -> bool {
+        self.is_pressed(code) && !self.previous_keys.contains(code)
+    }
+
+    // The pointer in canvas pixels, i.e. screen space.
+    pub fn mouse_position(&self) -> Point {
+        self.mouse
+    }
+
+    pub fn was_mouse_just_pressed(&self) -> bool {
+        self.mouse_down && !self.previous_mouse_down
+    }
+
     fn set_pressed(&mut self, code: &str, event: web_sys::KeyboardEvent) {
         self.pressed_keys.insert(code.into(), event);
     }
@@ -252,12 +610,30 @@ impl KeyState {
     fn set_released(&mut self, code: &str) {
         self.pressed_keys.remove(code);
     }
+
+    fn set_mouse(&mut self, event: &web_sys::MouseEvent, down: bool) {
+        self.mouse = Point::from_pixels(event.offset_x(), event.offset_y());
+        self.mouse_down = down;
+    }
+
+    fn end_update(&mut self) {
+        self.previous_keys = self.pressed_keys.keys().cloned().collect();
+        self.previous_mouse_down = self.mouse_down;
+    }
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, Hash, Deserialize, Serialize)]
 pub struct Point {
-    pub x: i16,
-    pub y: i16,
+    pub x: Fixed,
+    pub y: Fixed,
+}
+impl Point {
+    pub const fn from_pixels(x: i32, y: i32) -> Self {
+        Point {
+            x: Fixed::from_int(x),
+            y: Fixed::from_int(y),
+        }
+    }
 }
 
 #[derive(Deserialize, Clone)]
@@ -268,6 +644,12 @@ pub struct SheetRect {
     pub h: i16,
 }
 
+impl From<&SheetRect> for Rect {
+    fn from(rect: &SheetRect) -> Self {
+        Rect::new_from_x_y(rect.x.into(), rect.y.into(), rect.w.into(), rect.h.into())
+    }
+}
+
 #[derive(Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Cell {
@@ -275,89 +657,131 @@ pub struct Cell {
     pub sprite_source_size: SheetRect,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Default)]
 pub struct Sheet {
     pub frames: HashMap<String, Cell>,
 }
 
 pub struct SpriteSheet {
     sheet: Sheet,
-    image: HtmlImageElement,
+    texture: Texture,
 }
 impl SpriteSheet {
-    pub fn new(sheet: Sheet, image: HtmlImageElement) -> Self {
-        SpriteSheet { sheet, image }
+    pub fn new(sheet: Sheet, texture: Texture) -> Self {
+        SpriteSheet { sheet, texture }
     }
 
     pub fn cell(&self, name: &str) -> Option<&Cell> {
         self.sheet.frames.get(name)
     }
-    pub fn draw(&self, renderer: &Renderer, source: &Rect, destination: &Rect) {
-        renderer.draw_image(&self.image, source, destination);
-    }
-}
-
-pub struct Image {
-    element: HtmlImageElement,
-    bounding_box: Rect,
-}
-impl Image {
-    pub fn new(element: HtmlImageElement, position: Point) -> Self {
-        let bounding_box = Rect::new(position, element.width() as i16, element.height() as i16);
-        Image {
-            element,
-
-            bounding_box,
-        }
-    }
-    pub fn draw(&self, renderer: &Renderer) {
-        renderer.draw_entire_image(&self.element, &self.bounding_box.position);
-    }
-    pub fn bounding_box(&self) -> &Rect {
-        &self.bounding_box
-    }
-    pub fn draw_bounding_box(&self, renderer: &Renderer) {
-        renderer.draw_rect(self.bounding_box())
-    }
-    pub fn move_horizontally(&mut self, distance: i16) {
-        self.set_x(self.bounding_box.x() + distance);
-    }
-    pub fn set_x(&mut self, x: i16) {
-        self.bounding_box.set_x(x);
-    }
-    pub fn right(&self) -> i16 {
-        self.bounding_box.right()
+    pub fn draw(&self, renderer: &Renderer, source: &Rect, destination: &Rect) -> Result<()> {
+        renderer.draw_image(&self.texture, source, destination)
     }
 }
 
+// Headless audio, with no context, hands out sounds with no buffer and
+// plays nothing, so the game can run natively.
 #[derive(Clone)]
 pub struct Audio {
-    context: AudioContext,
+    context: Option<AudioContext>,
 }
 impl Audio {
     pub fn new() -> Result<Self> {
         Ok(Audio {
-            context: sound::create_audio_context()?,
+            context: Some(sound::create_audio_context()?),
         })
     }
+    #[cfg(test)]
+    pub fn headless() -> Self {
+        Audio { context: None }
+    }
+    fn context(&self) -> Result<&AudioContext> {
+        self.context
+            .as_ref()
+            .ok_or_else(|| anyhow!("Headless audio can't load sounds"))
+    }
     pub async fn load_sound(&self, filename: &str) -> Result<Sound> {
         let array_buffer = browser::fetch_array_buffer(filename).await?;
 
-        let audio_buffer = sound::decode_audio_data(&self.context, &array_buffer).await?;
+        let audio_buffer = sound::decode_audio_data(self.context()?, &array_buffer).await?;
 
         Ok(Sound {
-            buffer: audio_buffer,
+            buffer: Some(audio_buffer),
         })
     }
+    pub fn silence(&self) -> Result<Sound> {
+        match &self.context {
+            Some(context) => Ok(Sound {
+                buffer: Some(sound::create_silent_buffer(context)?),
+            }),
+            None => Ok(Sound { buffer: None }),
+        }
+    }
     pub fn play_sound(&self, sound: &Sound) -> Result<()> {
-        sound::play_sound(&self.context, &sound.buffer, sound::LOOPING::NO)
+        self.play(sound, sound::LOOPING::NO)
     }
     pub fn play_looping_sound(&self, sound: &Sound) -> Result<()> {
-        sound::play_sound(&self.context, &sound.buffer, sound::LOOPING::YES)
+        self.play(sound, sound::LOOPING::YES)
+    }
+    fn play(&self, sound: &Sound, looping: sound::LOOPING) -> Result<()> {
+        match (&self.context, &sound.buffer) {
+            (Some(context), Some(buffer)) => sound::play_sound(context, buffer, looping),
+            _ => Ok(()),
+        }
     }
 }
 
 #[derive(Clone)]
 pub struct Sound {
-    buffer: AudioBuffer,
+    buffer: Option<AudioBuffer>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zoomed(position: Point, zoom: i32) -> Camera {
+        Camera {
+            position,
+            zoom: Fixed::from_int(zoom),
+        }
+    }
+
+    fn xy(point: Point) -> (i32, i32) {
+        (point.x.to_pixels(), point.y.to_pixels())
+    }
+
+    #[test]
+    fn zooming_scales_the_offset_and_the_size() {
+        let camera = zoomed(Point::from_pixels(100, 40), 2);
+        let rect = camera.to_screen(&Rect::from_pixels(110, 50, 30, 20));
+
+        assert_eq!(xy(rect.position), (20, 20));
+        assert_eq!((rect.width.to_pixels(), rect.height.to_pixels()), (60, 40));
+        assert_eq!(xy(camera.to_world(rect.position)), (110, 50));
+    }
+
+    #[test]
+    fn following_puts_the_target_on_its_screen_position_on_both_axes() {
+        for zoom in [1, 2] {
+            let mut camera = zoomed(Point::default(), zoom);
+            let target = Point::from_pixels(900, 600);
+
+            camera.follow(target, Point::from_pixels(-20, 560));
+
+            let on_screen = camera.to_screen(&Rect::new(target, Fixed::ZERO, Fixed::ZERO));
+            assert_eq!(xy(on_screen.position), (-20, 560));
+        }
+    }
+
+    #[test]
+    fn a_decoded_texture_keeps_its_pixels_but_the_canvas_cannot_draw_it() {
+        let pixels: Rc<[u8]> = vec![255; 2 * 3 * 4].into();
+        let texture = Texture::from_rgba(2, 3, pixels.clone()).unwrap();
+
+        assert_eq!((texture.width(), texture.height()), (2, 3));
+        assert_eq!(texture.rgba(), Some(&pixels[..]));
+        assert!(texture.element().is_err());
+        assert!(Texture::from_rgba(2, 3, vec![0; 5].into()).is_err());
+    }
 }
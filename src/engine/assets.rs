@@ -0,0 +1,375 @@
+use super::{load_image, Audio, Sheet, Sound, Texture};
+use crate::browser;
+use anyhow::{anyhow, Result};
+use futures::future::join_all;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::rc::Rc;
+
+const PLACEHOLDER_IMAGE: &str = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAgAAAAICAIAAABLbSncAAAAEUlEQVR4nGP4z/AfK2IYWhIA0ad/gXfwoGMAAAAASUVORK5CYII=";
+
+#[derive(Clone, Deserialize, Default)]
+#[serde(default)]
+pub struct AssetManifest {
+    pub images: HashMap<String, String>,
+    pub sheets: HashMap<String, String>,
+    pub sounds: HashMap<String, String>,
+    pub data: HashMap<String, String>,
+    pub optional: OptionalAssets,
+}
+impl AssetManifest {
+    pub async fn fetch(path: &str) -> Result<Self, LoadError> {
+        let manifest = async { Ok(browser::fetch_json(path).await?.into_serde()?) };
+        manifest.await.map_err(|reason| LoadError {
+            failures: vec![AssetError {
+                kind: AssetKind::Manifest,
+                id: "manifest".to_string(),
+                path: path.to_string(),
+                reason,
+            }],
+        })
+    }
+    pub fn asset_count(&self) -> usize {
+        self.images.len() + self.sheets.len() + self.sounds.len() + self.data.len()
+    }
+}
+
+// Assets that get a placeholder instead of failing the load, listed by kind
+// since an image and a sheet usually share an id.
+#[derive(Clone, Deserialize, Default)]
+#[serde(default)]
+pub struct OptionalAssets {
+    pub images: HashSet<String>,
+    pub sheets: HashSet<String>,
+    pub sounds: HashSet<String>,
+}
+impl OptionalAssets {
+    pub fn contains(&self, kind: AssetKind, id: &str) -> bool {
+        match kind {
+            AssetKind::Image => self.images.contains(id),
+            AssetKind::Sheet => self.sheets.contains(id),
+            AssetKind::Sound => self.sounds.contains(id),
+            AssetKind::Manifest | AssetKind::Data => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum AssetKind {
+    Manifest,
+    Image,
+    Sheet,
+    Sound,
+    Data,
+}
+
+pub struct AssetError {
+    pub kind: AssetKind,
+    pub id: String,
+    pub path: String,
+    pub reason: anyhow::Error,
+}
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} '{}' ({}): {}",
+            self.kind, self.id, self.path, self.reason
+        )
+    }
+}
+impl fmt::Debug for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+impl std::error::Error for AssetError {}
+
+pub struct LoadError {
+    pub failures: Vec<AssetError>,
+}
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} asset(s) failed to load", self.failures.len())?;
+        for failure in &self.failures {
+            write!(f, "\n  {}", failure)?;
+        }
+        Ok(())
+    }
+}
+impl fmt::Debug for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+impl std::error::Error for LoadError {}
+
+#[derive(Default)]
+pub struct LoadProgress {
+    loaded: Cell<usize>,
+    total: Cell<usize>,
+}
+impl LoadProgress {
+    pub fn loaded(&self) -> usize {
+        self.loaded.get()
+    }
+    pub fn total(&self) -> usize {
+        self.total.get()
+    }
+    fn start(&self, total: usize) {
+        self.loaded.set(0);
+        self.total.set(total);
+    }
+    fn advance(&self) {
+        self.loaded.set(self.loaded.get() + 1);
+    }
+}
+
+pub struct Assets {
+    textures: HashMap<String, Texture>,
+    sheets: HashMap<String, Sheet>,
+    sounds: HashMap<String, Sound>,
+    data: HashMap<String, serde_json::Value>,
+    // Where every asset came from, to name the file when one turns out to
+    // be unusable after loading.
+    paths: AssetManifest,
+}
+impl Assets {
+    pub async fn load(
+        manifest: AssetManifest,
+        audio: &Audio,
+        progress: Rc<LoadProgress>,
+    ) -> Result<Self, LoadError> {
+        progress.start(manifest.asset_count());
+        let paths = manifest.clone();
+
+        let textures = load_all(
+            AssetKind::Image,
+            manifest.images,
+            &progress,
+            |path| async move { Ok(Texture::new(load_image(&path).await?)) },
+        );
+        let sheets = load_all(
+            AssetKind::Sheet,
+            manifest.sheets,
+            &progress,
+            |path| async move { Ok(browser::fetch_json(&path).await?.into_serde()?) },
+        );
+        let sounds = load_all(AssetKind::Sound, manifest.sounds, &progress, |path| {
+            let audio = audio.clone();
+            async move { audio.load_sound(&path).await }
+        });
+        let data = load_all(
+            AssetKind::Data,
+            manifest.data,
+            &progress,
+            |path| async move { Ok(browser::fetch_json(&path).await?.into_serde()?) },
+        );
+        let (
+            (textures, texture_failures),
+            (sheets, sheet_failures),
+            (sounds, sound_failures),
+            (data, data_failures),
+        ) = futures::join!(textures, sheets, sounds, data);
+
+        let mut assets = Assets {
+            textures,
+            sheets,
+            sounds,
+            data,
+            paths,
+        };
+        let mut fatal = Vec::new();
+        for failure in texture_failures
+            .into_iter()
+            .chain(sheet_failures)
+            .chain(sound_failures)
+            .chain(data_failures)
+        {
+            if !manifest.optional.contains(failure.kind, &failure.id) {
+                fatal.push(failure);
+                continue;
+            }
+            log!("Using placeholder for {}", failure);
+            if let Err(reason) = assets.insert_placeholder(&failure, audio).await {
+                fatal.push(AssetError { reason, ..failure });
+            }
+        }
+
+        if fatal.is_empty() {
+            Ok(assets)
+        } else {
+            Err(LoadError { failures: fatal })
+        }
+    }
+
+    async fn insert_placeholder(&mut self, failure: &AssetError, audio: &Audio) -> Result<()> {
+        let id = failure.id.clone();
+        match failure.kind {
+            AssetKind::Image => {
+                self.textures
+                    .insert(id, Texture::new(load_image(PLACEHOLDER_IMAGE).await?));
+            }
+            AssetKind::Sheet => {
+                self.sheets.insert(id, Sheet::default());
+            }
+            AssetKind::Sound => {
+                self.sounds.insert(id, audio.silence()?);
+            }
+            AssetKind::Manifest | AssetKind::Data => {
+                return Err(anyhow!("{:?} assets have no placeholder", failure.kind))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn texture(&self, id: &str) -> Result<Texture, AssetError> {
+        self.textures
+            .get(id)
+            .cloned()
+            .ok_or_else(|| self.missing(AssetKind::Image, id))
+    }
+    pub fn sheet(&self, id: &str) -> Result<Sheet, AssetError> {
+        self.sheets
+            .get(id)
+            .cloned()
+            .ok_or_else(|| self.missing(AssetKind::Sheet, id))
+    }
+    pub fn sound(&self, id: &str) -> Result<Sound, AssetError> {
+        self.sounds
+            .get(id)
+            .cloned()
+            .ok_or_else(|| self.missing(AssetKind::Sound, id))
+    }
+    pub fn data<T: DeserializeOwned>(&self, id: &str) -> Result<T, AssetError> {
+        let json = self
+            .data
+            .get(id)
+            .ok_or_else(|| self.missing(AssetKind::Data, id))?;
+        T::deserialize(json).map_err(|err| self.error(AssetKind::Data, id, err.into()))
+    }
+
+    pub fn path_of(&self, kind: AssetKind, id: &str) -> &str {
+        let paths = match kind {
+            AssetKind::Image => &self.paths.images,
+            AssetKind::Sheet => &self.paths.sheets,
+            AssetKind::Sound => &self.paths.sounds,
+            AssetKind::Data => &self.paths.data,
+            AssetKind::Manifest => return "",
+        };
+        paths.get(id).map_or("", String::as_str)
+    }
+
+    // Blames a loaded asset for something that went wrong using it, such as
+    // a data file that doesn't describe what it should.
+    pub fn error(&self, kind: AssetKind, id: &str, reason: anyhow::Error) -> AssetError {
+        AssetError {
+            kind,
+            id: id.to_string(),
+            path: self.path_of(kind, id).to_string(),
+            reason,
+        }
+    }
+
+    fn missing(&self, kind: AssetKind, id: &str) -> AssetError {
+        self.error(kind, id, anyhow!("No {:?} asset with id '{}'", kind, id))
+    }
+
+    // Reads the manifest's files straight from `directory`, to run the game
+    // natively: images only have their size read from the PNG header, and
+    // sounds are silent.
+    #[cfg(test)]
+    pub fn headless(manifest: AssetManifest, directory: &str) -> Result<Self> {
+        let paths = manifest.clone();
+        let read = |path: &str| {
+            std::fs::read(format!("{}/{}", directory, path))
+                .map_err(|err| anyhow!("Could not read {}: {}", path, err))
+        };
+        let mut textures = HashMap::new();
+        for (id, path) in manifest.images {
+            let png = read(&path)?;
+            let dimension = |offset: usize| -> Result<i16> {
+                let bytes = png
+                    .get(offset..offset + 4)
+                    .ok_or_else(|| anyhow!("{} is not a PNG", path))?;
+                Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i16)
+            };
+            textures.insert(id, Texture::headless(dimension(16)?, dimension(20)?));
+        }
+        let mut sheets = HashMap::new();
+        for (id, path) in manifest.sheets {
+            sheets.insert(id, serde_json::from_slice(&read(&path)?)?);
+        }
+        let mut sounds = HashMap::new();
+        for id in manifest.sounds.into_keys() {
+            sounds.insert(id, Audio::headless().silence()?);
+        }
+        let mut data = HashMap::new();
+        for (id, path) in manifest.data {
+            data.insert(id, serde_json::from_slice(&read(&path)?)?);
+        }
+        Ok(Assets {
+            textures,
+            sheets,
+            sounds,
+            data,
+            paths,
+        })
+    }
+}
+
+// Loads every entry concurrently and keeps going past failures, so the
+// caller can report every broken asset at once rather than only the first.
+async fn load_all<T, F, Fut>(
+    kind: AssetKind,
+    entries: HashMap<String, String>,
+    progress: &Rc<LoadProgress>,
+    load: F,
+) -> (HashMap<String, T>, Vec<AssetError>)
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let pending = entries.into_iter().map(|(id, path)| {
+        let loading = load(path.clone());
+        async move {
+            let result = loading.await;
+            progress.advance();
+            (id, path, result)
+        }
+    });
+
+    let mut loaded = HashMap::new();
+    let mut failures = Vec::new();
+    for (id, path, result) in join_all(pending).await {
+        match result {
+            Ok(asset) => {
+                loaded.insert(id, asset);
+            }
+            Err(reason) => failures.push(AssetError {
+                kind,
+                id,
+                path,
+                reason,
+            }),
+        }
+    }
+    (loaded, failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn optional_assets_are_matched_by_kind_and_id() {
+        let manifest: AssetManifest =
+            serde_json::from_str(r#"{ "optional": { "images": ["rhb"] } }"#).unwrap();
+        assert!(manifest.optional.contains(AssetKind::Image, "rhb"));
+        assert!(!manifest.optional.contains(AssetKind::Sheet, "rhb"));
+    }
+}
@@ -0,0 +1,258 @@
+use super::{Fixed, Point, Rect};
+
+// Stand-ins for "before the motion started" and "after it ended" on an axis
+// that overlaps the whole time; only how a time compares with 0 and 1 matters.
+const BEFORE: Fixed = Fixed::from_int(-1);
+const AFTER: Fixed = Fixed::from_int(2);
+
+// The side of the target that was run into, which is the opposite of the
+// direction the moving box was travelling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Collision {
+    pub side: Side,
+    // How far the moving box has to be pushed back out through `side` to
+    // stop overlapping.
+    pub depth: Fixed,
+    // How far through its motion it first touched; 0 if it was already
+    // inside the target when it set off.
+    pub time: Fixed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Impact {
+    // How far through its motion the box had got, from 0 to 1.
+    pub time: Fixed,
+    pub side: Side,
+}
+
+// Swept AABB: where a box moving by `motion` first starts overlapping
+// `target`, however far it travels in one go. There is no impact if the two
+// never overlap along the way, or if they already did before it set off.
+pub fn sweep(moving: &Rect, motion: Point, target: &Rect) -> Option<Impact> {
+    let (entry_x, exit_x) = overlap_times(
+        moving.x(),
+        moving.right(),
+        motion.x,
+        target.x(),
+        target.right(),
+    )?;
+    let (entry_y, exit_y) = overlap_times(
+        moving.y(),
+        moving.bottom(),
+        motion.y,
+        target.y(),
+        target.bottom(),
+    )?;
+
+    // Meeting a corner exactly counts as coming down on (or up into) it.
+    let (time, side) = if entry_y >= entry_x {
+        let side = if motion.y > Fixed::ZERO {
+            Side::Top
+        } else {
+            Side::Bottom
+        };
+        (entry_y, side)
+    } else {
+        let side = if motion.x > Fixed::ZERO {
+            Side::Left
+        } else {
+            Side::Right
+        };
+        (entry_x, side)
+    };
+
+    if time >= Fixed::ZERO && time < Fixed::from_int(1) && time < exit_x.min(exit_y) {
+        Some(Impact { time, side })
+    } else {
+        None
+    }
+}
+
+// Narrow phase for one pair of boxes: which side of `target` a box moving
+// by `motion` has run into, and how deep, whether it ends up inside the
+// target or swept right through it. A box that was already inside when it
+// set off is taken to have come in through the side it is least deep into.
+pub fn collide(moving: &Rect, motion: Point, target: &Rect) -> Option<Collision> {
+    let end = Rect::new_from_x_y(
+        moving.x() + motion.x,
+        moving.y() + motion.y,
+        moving.width,
+        moving.height,
+    );
+    let (side, time) = match sweep(moving, motion, target) {
+        Some(impact) => (impact.side, impact.time),
+        None if end.intersects(target) => {
+            let side = [Side::Top, Side::Bottom, Side::Left, Side::Right]
+                .into_iter()
+                .min_by_key(|side| depth(&end, target, *side))?;
+            (side, Fixed::ZERO)
+        }
+        None => return None,
+    };
+    Some(Collision {
+        side,
+        depth: depth(&end, target, side),
+        time,
+    })
+}
+
+fn depth(moving: &Rect, target: &Rect, side: Side) -> Fixed {
+    match side {
+        Side::Top => moving.bottom() - target.y(),
+        Side::Bottom => target.bottom() - moving.y(),
+        Side::Left => moving.right() - target.x(),
+        Side::Right => target.right() - moving.x(),
+    }
+}
+
+// When, as fractions of `motion`, the span from `start` to `end` starts and
+// stops overlapping the target's span on one axis.
+fn overlap_times(
+    start: Fixed,
+    end: Fixed,
+    motion: Fixed,
+    target_start: Fixed,
+    target_end: Fixed,
+) -> Option<(Fixed, Fixed)> {
+    if motion > Fixed::ZERO {
+        Some(((target_start - end) / motion, (target_end - start) / motion))
+    } else if motion < Fixed::ZERO {
+        Some(((target_end - start) / motion, (target_start - end) / motion))
+    } else if start < target_end && end > target_start {
+        Some((BEFORE, AFTER))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WALL: Rect = Rect::from_pixels(100, 100, 100, 100);
+
+    fn collision(side: Side, depth: i32, time: Fixed) -> Option<Collision> {
+        Some(Collision {
+            side,
+            depth: Fixed::from_int(depth),
+            time,
+        })
+    }
+
+    #[test]
+    fn coming_down_hits_the_top() {
+        assert_eq!(
+            collide(
+                &Rect::from_pixels(120, 40, 20, 40),
+                Point::from_pixels(5, 40),
+                &WALL
+            ),
+            collision(Side::Top, 20, Fixed::from_int(1) / 2)
+        );
+    }
+
+    #[test]
+    fn going_up_hits_the_bottom() {
+        assert_eq!(
+            collide(
+                &Rect::from_pixels(120, 210, 20, 40),
+                Point::from_pixels(5, -20),
+                &WALL
+            ),
+            collision(Side::Bottom, 10, Fixed::from_int(1) / 2)
+        );
+    }
+
+    #[test]
+    fn moving_across_hits_the_sides() {
+        assert_eq!(
+            collide(
+                &Rect::from_pixels(60, 150, 20, 40),
+                Point::from_pixels(40, 4),
+                &WALL
+            ),
+            collision(Side::Left, 20, Fixed::from_int(1) / 2)
+        );
+        assert_eq!(
+            collide(
+                &Rect::from_pixels(210, 150, 20, 40),
+                Point::from_pixels(-40, 4),
+                &WALL
+            ),
+            collision(Side::Right, 30, Fixed::from_int(1) / 4)
+        );
+    }
+
+    #[test]
+    fn side_of_a_tall_box_is_a_side_even_while_falling() {
+        assert_eq!(
+            collide(
+                &Rect::from_pixels(70, 110, 20, 40),
+                Point::from_pixels(20, 10),
+                &WALL
+            ),
+            collision(Side::Left, 10, Fixed::from_int(1) / 2)
+        );
+    }
+
+    #[test]
+    fn moving_right_through_a_thin_box_still_hits_it() {
+        let post = Rect::from_pixels(100, 0, 10, 200);
+        assert_eq!(
+            collide(
+                &Rect::from_pixels(50, 100, 20, 20),
+                Point::from_pixels(100, 0),
+                &post
+            ),
+            collision(Side::Left, 70, Fixed::from_int(3) / 10)
+        );
+    }
+
+    #[test]
+    fn already_inside_comes_out_of_the_nearest_side() {
+        assert_eq!(
+            collide(
+                &Rect::from_pixels(120, 95, 20, 40),
+                Point::from_pixels(5, 0),
+                &WALL
+            ),
+            collision(Side::Top, 35, Fixed::ZERO)
+        );
+        assert_eq!(
+            collide(
+                &Rect::from_pixels(185, 140, 20, 20),
+                Point::from_pixels(0, 5),
+                &WALL
+            ),
+            collision(Side::Right, 15, Fixed::ZERO)
+        );
+    }
+
+    #[test]
+    fn passing_by_or_just_touching_is_not_a_collision() {
+        assert_eq!(
+            collide(
+                &Rect::from_pixels(0, 0, 20, 20),
+                Point::from_pixels(60, 60),
+                &WALL
+            ),
+            None
+        );
+        assert_eq!(
+            collide(
+                &Rect::from_pixels(120, 60, 20, 40),
+                Point::from_pixels(10, 0),
+                &WALL
+            ),
+            None
+        );
+    }
+}
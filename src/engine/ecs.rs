@@ -0,0 +1,499 @@
+use super::grid::Grid;
+use super::{Cell, Fixed, Point, Rect, Renderer, SpriteSheet, Texture};
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+// Entities are never reused, so every storage iterates in the order things
+// were spawned and a stale handle can't alias something spawned later.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Entity(u64);
+
+pub type Storage<T> = BTreeMap<Entity, T>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColliderKind {
+    // Stood on from above; bumped into from below or the side.
+    Solid,
+    // Solid, and settled on before anything else is checked.
+    Ground,
+    // Harmful to touch from any direction.
+    Hazard,
+    // Harmful to anything standing up, but passed safely by ducking under.
+    Overhead,
+    // Blocks nothing; what touching it means is up to its behaviour.
+    Sensor,
+}
+
+#[derive(Clone, Hash)]
+pub struct Collider {
+    pub kind: ColliderKind,
+    // Relative to the entity's position.
+    pub bounding_boxes: Vec<Rect>,
+}
+
+#[derive(Clone)]
+pub enum Sprite {
+    // A whole image, turned by `angle` radians about the middle of its base.
+    Image {
+        texture: Texture,
+        angle: f64,
+    },
+    // Named cells drawn left to right, shifted by `offset` without moving
+    // the entity.
+    Cells {
+        sheet: Rc<SpriteSheet>,
+        names: Vec<String>,
+        offset: Point,
+    },
+    // One named cell, placed by its trim offset like an animation frame.
+    Frame {
+        sheet: Rc<SpriteSheet>,
+        name: String,
+    },
+}
+impl Sprite {
+    fn draw(&self, renderer: &Renderer, position: Point) -> Result<()> {
+        match self {
+            Sprite::Image { texture, angle } if *angle == 0.0 => {
+                renderer.draw_entire_image(texture, &position)
+            }
+            Sprite::Image { texture, angle } => renderer.draw_rotated_image(
+                texture,
+                &position,
+                &Point {
+                    x: position.x + Fixed::from(texture.width()) / 2,
+                    y: position.y + texture.height().into(),
+                },
+                *angle,
+            ),
+            Sprite::Cells {
+                sheet,
+                names,
+                offset,
+            } => {
+                let cells = names
+                    .iter()
+                    .map(|name| find_cell(sheet, name))
+                    .collect::<Result<Vec<&Cell>>>()?;
+                let mut x = position.x + offset.x;
+                for cell in cells {
+                    sheet.draw(
+                        renderer,
+                        &Rect::from(&cell.frame),
+                        &Rect::new_from_x_y(
+                            x,
+                            position.y + offset.y,
+                            cell.frame.w.into(),
+                            cell.frame.h.into(),
+                        ),
+                    )?;
+                    x += cell.frame.w.into();
+                }
+                Ok(())
+            }
+            Sprite::Frame { sheet, name } => {
+                let cell = find_cell(sheet, name)?;
+                sheet.draw(
+                    renderer,
+                    &Rect::from(&cell.frame),
+                    &Rect::new_from_x_y(
+                        position.x + cell.sprite_source_size.x.into(),
+                        position.y + cell.sprite_source_size.y.into(),
+                        cell.frame.w.into(),
+                        cell.frame.h.into(),
+                    ),
+                )
+            }
+        }
+    }
+
+    fn right(&self, position: Point) -> Fixed {
+        match self {
+            Sprite::Image { texture, .. } => position.x + texture.width().into(),
+            Sprite::Cells {
+                sheet,
+                names,
+                offset,
+            } => names
+                .iter()
+                .filter_map(|name| sheet.cell(name))
+                .fold(position.x + offset.x, |x, cell| x + cell.frame.w.into()),
+            Sprite::Frame { sheet, name } => match sheet.cell(name) {
+                Some(cell) => {
+                    position.x + cell.sprite_source_size.x.into() + Fixed::from(cell.frame.w)
+                }
+                None => position.x,
+            },
+        }
+    }
+}
+
+// A missing cell fails the whole sprite, so it is drawn as a placeholder and
+// the error reported rather than quietly left out.
+fn find_cell<'a>(sheet: &'a SpriteSheet, name: &str) -> Result<&'a Cell> {
+    sheet
+        .cell(name)
+        .ok_or_else(|| anyhow!("Cell not found: {}", name))
+}
+
+// Everything in a scene is an entity with whichever of these components it
+// needs. `B` is the game's own behaviour component; the engine only moves,
+// draws and measures entities and leaves what they do to the game.
+// Positions and colliders are only changed through the world, which keeps
+// its broad phase grid in step with them.
+pub struct World<B> {
+    entities: BTreeSet<Entity>,
+    next: u64,
+    positions: Storage<Point>,
+    pub velocities: Storage<Point>,
+    pub sprites: Storage<Sprite>,
+    colliders: Storage<Collider>,
+    pub behaviours: Storage<B>,
+    grid: Grid,
+}
+impl<B> Default for World<B> {
+    fn default() -> Self {
+        World {
+            entities: BTreeSet::new(),
+            next: 0,
+            positions: Storage::new(),
+            velocities: Storage::new(),
+            sprites: Storage::new(),
+            colliders: Storage::new(),
+            behaviours: Storage::new(),
+            grid: Grid::default(),
+        }
+    }
+}
+impl<B> World<B> {
+    pub fn new() -> Self {
+        World::default()
+    }
+
+    pub fn spawn(&mut self, position: Point) -> Entity {
+        let entity = Entity(self.next);
+        self.next += 1;
+        self.entities.insert(entity);
+        self.positions.insert(entity, position);
+        entity
+    }
+
+    pub fn despawn(&mut self, entity: Entity) {
+        self.entities.remove(&entity);
+        self.positions.remove(&entity);
+        self.velocities.remove(&entity);
+        self.sprites.remove(&entity);
+        self.colliders.remove(&entity);
+        self.behaviours.remove(&entity);
+        self.grid.remove(entity);
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.entities.contains(&entity)
+    }
+
+    pub fn position(&self, entity: Entity) -> Point {
+        self.positions.get(&entity).copied().unwrap_or_default()
+    }
+
+    pub fn set_position(&mut self, entity: Entity, position: Point) {
+        if self.contains(entity) {
+            self.positions.insert(entity, position);
+            self.regrid(entity);
+        }
+    }
+
+    pub fn collider(&self, entity: Entity) -> Option<&Collider> {
+        self.colliders.get(&entity)
+    }
+
+    pub fn set_collider(&mut self, entity: Entity, collider: Collider) {
+        if self.contains(entity) {
+            self.colliders.insert(entity, collider);
+            self.regrid(entity);
+        }
+    }
+
+    pub fn remove_collider(&mut self, entity: Entity) {
+        self.colliders.remove(&entity);
+        self.grid.remove(entity);
+    }
+
+    fn regrid(&mut self, entity: Entity) {
+        if let Some(bounds) = Rect::enclosing(&self.bounding_boxes(entity)) {
+            self.grid.insert(entity, &bounds);
+        }
+    }
+
+    // The broad phase: every entity with a collider that might touch `area`,
+    // in the order they were spawned. Only the cells `area` covers are
+    // looked at, so this costs the same however big the world gets.
+    pub fn colliders_near(&self, area: &Rect) -> Vec<Entity> {
+        self.grid.query(area).into_iter().collect()
+    }
+
+    // The collider's boxes in world coordinates, empty without a collider.
+    pub fn bounding_boxes(&self, entity: Entity) -> Vec<Rect> {
+        let position = self.position(entity);
+        self.colliders
+            .get(&entity)
+            .map(|collider| {
+                collider
+                    .bounding_boxes
+                    .iter()
+                    .map(|bounding_box| {
+                        Rect::new_from_x_y(
+                            position.x + bounding_box.x(),
+                            position.y + bounding_box.y(),
+                            bounding_box.width,
+                            bounding_box.height,
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // How far right the entity reaches: its collider if it has one, since
+    // that is what the boy runs into, otherwise whatever it draws.
+    pub fn right(&self, entity: Entity) -> Fixed {
+        let bounding_boxes = self.bounding_boxes(entity);
+        match (
+            bounding_boxes.iter().map(Rect::right).max(),
+            self.sprites.get(&entity),
+        ) {
+            (Some(right), _) => right,
+            (None, Some(sprite)) => sprite.right(self.position(entity)),
+            (None, None) => self.position(entity).x,
+        }
+    }
+
+    pub fn rightmost(&self, entities: &[Entity]) -> Fixed {
+        entities
+            .iter()
+            .map(|entity| self.right(*entity))
+            .max()
+            .unwrap_or(Fixed::ZERO)
+    }
+
+    pub fn retain(&mut self, keep: impl Fn(&Self, Entity) -> bool) {
+        let gone: Vec<Entity> = self
+            .entities
+            .iter()
+            .copied()
+            .filter(|entity| !keep(self, *entity))
+            .collect();
+        for entity in gone {
+            self.despawn(entity);
+        }
+    }
+
+    pub fn movement(&mut self) {
+        let moving: Vec<(Entity, Point)> = self
+            .velocities
+            .iter()
+            .filter(|(_, velocity)| velocity.x != Fixed::ZERO || velocity.y != Fixed::ZERO)
+            .map(|(entity, velocity)| (*entity, *velocity))
+            .collect();
+        for (entity, velocity) in moving {
+            let position = self.position(entity);
+            self.set_position(
+                entity,
+                Point {
+                    x: position.x + velocity.x,
+                    y: position.y + velocity.y,
+                },
+            );
+        }
+    }
+
+    // A sprite that can't be drawn leaves a placeholder over its collider so
+    // the missing art is obvious without hiding what the boy can hit.
+    pub fn render(&self, renderer: &Renderer) {
+        for (entity, sprite) in &self.sprites {
+            let result = sprite.draw(renderer, self.position(*entity));
+            if result.is_err() {
+                for bounding_box in self.bounding_boxes(*entity) {
+                    renderer.draw_placeholder(&bounding_box);
+                }
+            }
+            renderer.collect(result);
+        }
+    }
+
+    pub fn render_bounding_boxes(&self, renderer: &Renderer) {
+        for entity in self.colliders.keys() {
+            for bounding_box in self.bounding_boxes(*entity) {
+                renderer.draw_rect(&bounding_box);
+            }
+        }
+    }
+}
+
+// Everything that decides how the world plays out from here, and nothing
+// about how it looks: sprites are left out, and the grid is rebuilt from
+// positions and colliders anyway. Storages iterate in spawn order, so two
+// worlds stepped through the same ticks hash the same.
+impl<B: Hash> Hash for World<B> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.next.hash(state);
+        for entity in &self.entities {
+            entity.hash(state);
+            self.positions.get(entity).hash(state);
+            self.velocities.get(entity).hash(state);
+            self.colliders.get(entity).hash(state);
+            self.behaviours.get(entity).hash(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::state_hash;
+    use std::time::Instant;
+
+    const PLATFORM_BOXES: [Rect; 3] = [
+        Rect::from_pixels(0, 0, 60, 54),
+        Rect::from_pixels(60, 0, 264, 93),
+        Rect::from_pixels(324, 0, 60, 54),
+    ];
+
+    fn spawn_obstacle(world: &mut World<()>, x: i32, y: i32) -> Entity {
+        let entity = world.spawn(Point::from_pixels(x, y));
+        world.set_collider(
+            entity,
+            Collider {
+                kind: ColliderKind::Solid,
+                bounding_boxes: PLATFORM_BOXES.to_vec(),
+            },
+        );
+        entity
+    }
+
+    // A course of `count` still platforms, one every 500px alternating high
+    // and low, except that every tenth one the boy runs past is rolling.
+    fn course(count: i32) -> World<()> {
+        let mut world = World::new();
+        for index in 0..count {
+            let platform = spawn_obstacle(&mut world, index * 500, 300 + index % 2 * 100);
+            if index < 50 && index % 10 == 0 {
+                world.velocities.insert(platform, Point::from_pixels(-1, 0));
+            }
+        }
+        world
+    }
+
+    // Runs a boy-sized box across the first 50 platforms, returning how many
+    // candidates the broad phase handed back over the whole run.
+    fn run(world: &mut World<()>) -> usize {
+        let mut candidates = 0;
+        for tick in 0..2500 {
+            world.movement();
+            let boy = Rect::from_pixels(tick * 10, 380, 70, 120);
+            candidates += world.colliders_near(&boy).len();
+        }
+        candidates
+    }
+
+    #[test]
+    fn finds_colliders_near_an_area_in_spawn_order() {
+        let mut world = World::<()>::new();
+        let far = spawn_obstacle(&mut world, 5000, 400);
+        let second = spawn_obstacle(&mut world, 300, 400);
+        let first = spawn_obstacle(&mut world, 0, 400);
+
+        assert_eq!(
+            world.colliders_near(&Rect::from_pixels(250, 420, 100, 50)),
+            vec![second, first]
+        );
+        assert_eq!(
+            world.colliders_near(&Rect::from_pixels(5000, 420, 10, 10)),
+            vec![far]
+        );
+        assert!(world
+            .colliders_near(&Rect::from_pixels(2000, 0, 100, 100))
+            .is_empty());
+    }
+
+    #[test]
+    fn moved_and_removed_colliders_are_kept_up_to_date() {
+        let mut world = World::<()>::new();
+        let moving = spawn_obstacle(&mut world, 0, 400);
+        let area = Rect::from_pixels(1000, 420, 10, 10);
+        assert!(world.colliders_near(&area).is_empty());
+
+        world.velocities.insert(moving, Point::from_pixels(100, 0));
+        for _ in 0..8 {
+            world.movement();
+        }
+        assert_eq!(world.colliders_near(&area), vec![moving]);
+        assert!(world
+            .colliders_near(&Rect::from_pixels(0, 420, 10, 10))
+            .is_empty());
+
+        world.remove_collider(moving);
+        assert!(world.colliders_near(&area).is_empty());
+
+        let despawned = spawn_obstacle(&mut world, 1000, 400);
+        world.despawn(despawned);
+        assert!(world.colliders_near(&area).is_empty());
+    }
+
+    fn hashes(mut world: World<()>, ticks: usize) -> Vec<u64> {
+        (0..ticks)
+            .map(|_| {
+                world.movement();
+                state_hash(&world)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_same_ticks_hash_the_same_and_any_difference_shows() {
+        let run = hashes(course(60), 100);
+        assert_eq!(run, hashes(course(60), 100));
+
+        let mut nudged = course(60);
+        let last = Entity(59);
+        nudged.velocities.insert(
+            last,
+            Point {
+                x: Fixed::from_int(1) / 65536,
+                y: Fixed::ZERO,
+            },
+        );
+        let nudged = hashes(nudged, 100);
+        assert!(run.iter().zip(&nudged).all(|(run, nudged)| run != nudged));
+    }
+
+    // Pinned, so hashing the world differently on some target, or changing
+    // what goes into the hash, fails here instead of desyncing replays.
+    #[test]
+    fn world_hash_is_the_same_on_every_build() {
+        assert_eq!(hashes(course(3), 10).last(), Some(&0x4264_1874_632b_645a));
+    }
+
+    #[test]
+    fn thousands_more_obstacles_add_no_work_per_tick() {
+        assert_eq!(run(&mut course(100)), run(&mut course(10_000)));
+    }
+
+    // cargo test --release benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn benchmark_broad_phase() {
+        for count in [100, 1_000, 10_000, 50_000] {
+            let mut world = course(count);
+            let start = Instant::now();
+            run(&mut world);
+            println!(
+                "{:>6} obstacles: {:>6.2}us per tick",
+                count,
+                start.elapsed().as_secs_f64() * 1e6 / 2500.0
+            );
+        }
+    }
+}
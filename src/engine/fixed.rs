@@ -0,0 +1,104 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+const FRACTION_BITS: u32 = 16;
+const ONE: i64 = 1 << FRACTION_BITS;
+
+// World-space scalar: a 48.16 fixed-point number, so positions have sub-pixel
+// precision and a run would have to last for years before overflowing.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed(i64);
+
+impl Fixed {
+    pub const ZERO: Fixed = Fixed(0);
+
+    pub const fn from_int(value: i32) -> Self {
+        Fixed((value as i64) << FRACTION_BITS)
+    }
+    pub fn to_pixels(self) -> i32 {
+        ((self.0 + ONE / 2) >> FRACTION_BITS) as i32
+    }
+    pub fn floor(self) -> i32 {
+        (self.0 >> FRACTION_BITS) as i32
+    }
+    pub fn rem_euclid(self, rhs: Fixed) -> Self {
+        Fixed(self.0.rem_euclid(rhs.0))
+    }
+    pub fn abs(self) -> Self {
+        Fixed(self.0.abs())
+    }
+}
+
+// Data files write world values as plain JSON numbers; they are rounded to
+// the nearest fixed-point step once, at load time.
+impl<'de> Deserialize<'de> for Fixed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = f64::deserialize(deserializer)?;
+        Ok(Fixed((value * ONE as f64).round() as i64))
+    }
+}
+
+impl Serialize for Fixed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.0 as f64 / ONE as f64)
+    }
+}
+
+impl From<i16> for Fixed {
+    fn from(value: i16) -> Self {
+        Fixed::from_int(value.into())
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+    fn add(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0 + rhs.0)
+    }
+}
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, rhs: Fixed) {
+        self.0 += rhs.0;
+    }
+}
+impl Sub for Fixed {
+    type Output = Fixed;
+    fn sub(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0 - rhs.0)
+    }
+}
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, rhs: Fixed) {
+        self.0 -= rhs.0;
+    }
+}
+impl Neg for Fixed {
+    type Output = Fixed;
+    fn neg(self) -> Fixed {
+        Fixed(-self.0)
+    }
+}
+impl Mul for Fixed {
+    type Output = Fixed;
+    fn mul(self, rhs: Fixed) -> Fixed {
+        Fixed(((self.0 as i128 * rhs.0 as i128) >> FRACTION_BITS) as i64)
+    }
+}
+impl Div for Fixed {
+    type Output = Fixed;
+    fn div(self, rhs: Fixed) -> Fixed {
+        Fixed((((self.0 as i128) << FRACTION_BITS) / rhs.0 as i128) as i64)
+    }
+}
+impl Mul<i32> for Fixed {
+    type Output = Fixed;
+    fn mul(self, rhs: i32) -> Fixed {
+        Fixed(self.0 * rhs as i64)
+    }
+}
+impl Div<i32> for Fixed {
+    type Output = Fixed;
+    fn div(self, rhs: i32) -> Fixed {
+        Fixed(self.0 / rhs as i64)
+    }
+}
@@ -0,0 +1,72 @@
+use super::{Entity, Fixed, Rect};
+use std::collections::{BTreeSet, HashMap};
+
+const CELL_SIZE: i32 = 128;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct CellRange {
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+}
+impl CellRange {
+    fn covering(area: &Rect) -> Self {
+        let cell = |value: Fixed| value.floor().div_euclid(CELL_SIZE);
+        CellRange {
+            left: cell(area.x()),
+            top: cell(area.y()),
+            right: cell(area.right()),
+            bottom: cell(area.bottom()),
+        }
+    }
+
+    fn cells(self) -> impl Iterator<Item = (i32, i32)> {
+        (self.left..=self.right).flat_map(move |x| (self.top..=self.bottom).map(move |y| (x, y)))
+    }
+}
+
+// Broad phase: a uniform grid of every collider's bounds, kept up to date as
+// entities move, so finding what might touch an area only looks at the cells
+// it covers however many entities the world holds.
+#[derive(Default)]
+pub struct Grid {
+    cells: HashMap<(i32, i32), Vec<Entity>>,
+    occupied: HashMap<Entity, CellRange>,
+}
+impl Grid {
+    pub fn insert(&mut self, entity: Entity, bounds: &Rect) {
+        let range = CellRange::covering(bounds);
+        if self.occupied.get(&entity) == Some(&range) {
+            return;
+        }
+        self.remove(entity);
+        for cell in range.cells() {
+            self.cells.entry(cell).or_default().push(entity);
+        }
+        self.occupied.insert(entity, range);
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some(range) = self.occupied.remove(&entity) {
+            for cell in range.cells() {
+                if let Some(entities) = self.cells.get_mut(&cell) {
+                    entities.retain(|other| *other != entity);
+                    if entities.is_empty() {
+                        self.cells.remove(&cell);
+                    }
+                }
+            }
+        }
+    }
+
+    // Everything sharing a cell with `area`, in the order it was spawned.
+    pub fn query(&self, area: &Rect) -> BTreeSet<Entity> {
+        CellRange::covering(area)
+            .cells()
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .collect()
+    }
+}
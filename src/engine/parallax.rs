@@ -0,0 +1,84 @@
+use super::{Assets, Camera, Fixed, Point, Renderer, Texture};
+use anyhow::Result;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayerDefinition {
+    pub image: String,
+    pub scroll_factor: Fixed,
+    #[serde(default)]
+    pub offset_y: Fixed,
+    #[serde(default = "tiled_by_default")]
+    pub tile: bool,
+}
+
+fn tiled_by_default() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+pub struct ParallaxDefinition {
+    pub layers: Vec<LayerDefinition>,
+}
+
+struct Layer {
+    texture: Texture,
+    scroll_factor: Fixed,
+    offset_y: Fixed,
+    tile: bool,
+}
+
+pub struct Parallax {
+    layers: Vec<Layer>,
+}
+impl Parallax {
+    pub fn new(definition: ParallaxDefinition, assets: &Assets) -> Result<Self> {
+        let layers = definition
+            .layers
+            .into_iter()
+            .map(|layer| {
+                Ok(Layer {
+                    texture: assets.texture(&layer.image)?,
+                    scroll_factor: layer.scroll_factor,
+                    offset_y: layer.offset_y,
+                    tile: layer.tile,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Parallax { layers })
+    }
+
+    // Layers are drawn back to front in screen space, each shifted by its
+    // share of the camera's travel.
+    pub fn draw(&self, renderer: &Renderer, camera: &Camera) -> Result<()> {
+        let viewport_width = renderer.viewport_width();
+        for layer in &self.layers {
+            let scroll = camera.left() * layer.scroll_factor;
+            let width = Fixed::from(layer.texture.width());
+            if !layer.tile || width <= Fixed::ZERO {
+                renderer.draw_entire_image(
+                    &layer.texture,
+                    &Point {
+                        x: -scroll,
+                        y: layer.offset_y,
+                    },
+                )?;
+                continue;
+            }
+
+            let mut x = -scroll.rem_euclid(width);
+            while x < viewport_width {
+                renderer.draw_entire_image(
+                    &layer.texture,
+                    &Point {
+                        x,
+                        y: layer.offset_y,
+                    },
+                )?;
+                x += width;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use std::hash::{Hash, Hasher};
+
+const OFFSET_BASIS: u64 = 0xcbf29ce4_84222325;
+const PRIME: u64 = 0x100_0000_01b3;
+
+// FNV-1a over exactly the bytes it is fed. The std hashers are free to change
+// between releases and hash integers in native byte order and width, so two
+// builds of the same run could disagree; this one writes every integer little
+// endian and `usize`/`isize` as 64 bits, so a wasm32 build in any browser and
+// a native build give the same hash for the same state.
+pub struct StateHasher(u64);
+impl Default for StateHasher {
+    fn default() -> Self {
+        StateHasher(OFFSET_BASIS)
+    }
+}
+impl Hasher for StateHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(PRIME);
+        }
+    }
+    fn write_u16(&mut self, value: u16) {
+        self.write(&value.to_le_bytes());
+    }
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+    fn write_u128(&mut self, value: u128) {
+        self.write(&value.to_le_bytes());
+    }
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+    fn write_i16(&mut self, value: i16) {
+        self.write_u16(value as u16);
+    }
+    fn write_i32(&mut self, value: i32) {
+        self.write_u32(value as u32);
+    }
+    fn write_i64(&mut self, value: i64) {
+        self.write_u64(value as u64);
+    }
+    fn write_i128(&mut self, value: i128) {
+        self.write_u128(value as u128);
+    }
+    fn write_isize(&mut self, value: isize) {
+        self.write_i64(value as i64);
+    }
+}
+
+pub fn state_hash(value: &impl Hash) -> u64 {
+    let mut hasher = StateHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_published_fnv_1a_values() {
+        let mut hasher = StateHasher::default();
+        assert_eq!(hasher.finish(), 0xcbf29ce484222325);
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn integers_hash_as_the_same_bytes_on_every_target() {
+        assert_eq!(state_hash(&5usize), state_hash(&5u64));
+        assert_eq!(state_hash(&-5isize), state_hash(&-5i64));
+
+        let mut bytes = StateHasher::default();
+        bytes.write(&[2, 1, 0, 0]);
+        assert_eq!(state_hash(&0x0102u32), bytes.finish());
+    }
+}
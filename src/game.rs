@@ -1,177 +1,683 @@
-use crate::engine::{Audio, Game, Image, KeyState, Point, Rect, Renderer, Sheet, SpriteSheet};
-use crate::{browser, engine};
+use crate::browser;
+use crate::engine::{
+    state_hash, AssetError, AssetKind, AssetManifest, Assets, Audio, Camera, Entity, Fixed, Game,
+    KeyState, LoadError, LoadProgress, Parallax, ParallaxDefinition, Point, Rect, Renderer, Sound,
+    SpriteSheet,
+};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use obstacles::{rightmost, Obstacle};
-use rand::{thread_rng, Rng};
-use rhb::RedHatBoy;
+use difficulty::DifficultyCurve;
+use editor::Editor;
+use ground::{Ground, GROUND_SHOWN, GROUND_SHOWN_PIXELS, GROUND_TOP};
+use levels::{build_course, Course, LevelEntry, LevelSelect, Mode};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use reachability::{place_next_segment, Placement};
+use rhb::{PowerUp, RedHatBoy};
+use segments::SegmentLibrary;
+use std::cell::RefCell;
 use std::rc::Rc;
-use web_sys::HtmlImageElement;
+use world::GameWorld;
 
+mod difficulty;
+mod editor;
+mod enemies;
+mod ground;
+mod levels;
 mod obstacles;
+mod reachability;
 mod rhb;
 mod segments;
+mod world;
 
 pub const HEIGHT: i16 = 600;
-pub const TIMELINE_MINIMUM: i16 = 1000;
-pub const OBSTACLE_BUFFER: i16 = 20;
+pub const TIMELINE_MINIMUM: Fixed = Fixed::from_int(1000);
+pub const OBSTACLE_BUFFER: Fixed = Fixed::from_int(20);
+const FALLBACK_GAP: Fixed = Fixed::from_int(400);
+// Where the camera keeps the boy's x and the top of the ground on screen.
+const FOLLOW_SCREEN_POSITION: Point = Point::from_pixels(-20, HEIGHT as i32 - GROUND_SHOWN_PIXELS);
+const ASSET_MANIFEST: &str = "assets.json";
+const MUSIC: &str = "background_music";
+const LOADING_BAR: Rect = Rect::from_pixels(100, 290, 400, 20);
+const TEXT_COLOR: &str = "#000000";
+const SCORE_POSITION: Point = Point::from_pixels(10, 30);
+const POWER_UP_HUD_POSITION: Point = Point::from_pixels(10, 55);
+// Every segment keeps its first obstacle well clear of its left edge, so a
+// power-up dropped here never sits inside anything.
+const POWER_UP_POSITION: Point = Point::from_pixels(40, 420);
+const TICKS_PER_SECOND: u32 = 60;
+const FINISH_COLOR: &str = "#FFFFFF";
+const FINISH_LINE_WIDTH: Fixed = Fixed::from_int(10);
 
 pub enum WalkTheDog {
-    Loading,
-    Loaded(Walk),
+    Loading(Option<LoadingScreen>),
+    Selecting(LevelSelect),
+    Loaded(Box<Walk>),
 }
 impl WalkTheDog {
     pub fn new() -> Self {
-        WalkTheDog::Loading
+        WalkTheDog::Loading(None)
     }
 }
 
-pub struct Walk {
-    boy: RedHatBoy,
-    backgrounds: [Image; 2],
-    obstacles: Vec<Box<dyn Obstacle>>,
-    obstacle_sheet: Rc<SpriteSheet>,
-    stone: HtmlImageElement,
-    timeline: i16,
+pub struct LoadingScreen {
+    audio: Audio,
+    progress: Rc<LoadProgress>,
+    result: Rc<RefCell<Option<Result<Assets, LoadError>>>>,
+    failure: Option<LoadError>,
 }
-impl Walk {
-    fn velocity(&self) -> i16 {
-        -self.boy.walking_speed()
+impl LoadingScreen {
+    fn start(audio: Audio) -> Self {
+        let progress = Rc::new(LoadProgress::default());
+        let result = Rc::new(RefCell::new(None));
+
+        let loader_audio = audio.clone();
+        let loader_progress = progress.clone();
+        let loader_result = result.clone();
+        browser::spawn_local(async move {
+            let assets = match AssetManifest::fetch(ASSET_MANIFEST).await {
+                Ok(manifest) => Assets::load(manifest, &loader_audio, loader_progress).await,
+                Err(err) => Err(err),
+            };
+            *loader_result.borrow_mut() = Some(assets);
+        });
+
+        LoadingScreen {
+            audio,
+            progress,
+            result,
+            failure: None,
+        }
     }
-    fn generate_next_segment(&mut self) {
-        let mut rng = thread_rng();
-        let next_segment = rng.gen_range(0..3);
-
-        let mut next_obstacles = match next_segment {
-            0 => segments::stone_and_platform(
-                self.stone.clone(),
-                self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
-            ),
-            1 => segments::platform_and_stone(
-                self.stone.clone(),
-                self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
-            ),
-            2 => segments::stone_on_low_platform(
-                self.stone.clone(),
-                self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
+    fn update(&mut self, keystate: &KeyState) -> Option<LevelSelect> {
+        if self.failure.is_some() && keystate.was_just_pressed("Enter") {
+            *self = LoadingScreen::start(self.audio.clone());
+        }
+
+        let assets = self.result.borrow_mut().take()?;
+        match assets.and_then(|assets| {
+            start_game(assets, self.audio.clone()).map_err(|failure| LoadError {
+                failures: vec![failure],
+            })
+        }) {
+            Ok(menu) => Some(menu),
+            Err(err) => {
+                log!("Could not load assets {:#?}", err);
+                self.failure = Some(err);
+                None
+            }
+        }
+    }
+    fn draw(&self, renderer: &Renderer) {
+        match &self.failure {
+            Some(failure) => self.draw_failure(renderer, failure),
+            None => self.draw_progress(renderer),
+        }
+    }
+    fn draw_progress(&self, renderer: &Renderer) {
+        let mut filled = LOADING_BAR;
+        filled.width =
+            LOADING_BAR.width * self.progress.loaded() as i32 / self.progress.total().max(1) as i32;
+
+        renderer.fill_rect(&filled, TEXT_COLOR);
+        renderer.draw_rect(&LOADING_BAR);
+        renderer.collect(renderer.draw_text(
+            &format!(
+                "Loading {}/{}",
+                self.progress.loaded(),
+                self.progress.total()
             ),
-            _ => vec![],
-        };
-        self.timeline = rightmost(&next_obstacles);
-        self.obstacles.append(&mut next_obstacles)
+            &Point {
+                x: LOADING_BAR.x(),
+                y: LOADING_BAR.y() - Fixed::from_int(10),
+            },
+            TEXT_COLOR,
+        ));
+    }
+    fn draw_failure(&self, renderer: &Renderer, failure: &LoadError) {
+        let mut position = Point::from_pixels(20, 40);
+        renderer.collect(renderer.draw_text("Could not load the game:", &position, TEXT_COLOR));
+        for asset in &failure.failures {
+            position.y += Fixed::from_int(30);
+            renderer.collect(renderer.draw_text(
+                &format!("{:?} '{}' ({})", asset.kind, asset.id, asset.path),
+                &position,
+                TEXT_COLOR,
+            ));
+            position.y += Fixed::from_int(20);
+            let reason: String = asset.reason.to_string().chars().take(70).collect();
+            renderer.collect(renderer.draw_text(&reason, &position, TEXT_COLOR));
+        }
+        position.y += Fixed::from_int(40);
+        renderer.collect(renderer.draw_text("Press Enter to retry", &position, TEXT_COLOR));
     }
 }
 
-#[async_trait(?Send)]
-impl Game for WalkTheDog {
-    async fn initalize(&self) -> Result<Box<dyn Game>> {
-        match self {
-            WalkTheDog::Loading => {
-                let sheet = browser::fetch_json("rhb.json").await?.into_serde()?;
+// The music starts once, when everything is loaded, and keeps looping across
+// every run started from the level select.
+fn start_game(assets: Assets, audio: Audio) -> Result<LevelSelect, AssetError> {
+    audio
+        .play_looping_sound(&assets.sound(MUSIC)?)
+        .map_err(|err| assets.error(AssetKind::Sound, MUSIC, err))?;
+    LevelSelect::new(Rc::new(assets), audio)
+}
 
-                let background = engine::load_image("BG.png").await?;
-                let background_width = background.width() as i16;
-                let stone = engine::load_image("Stone.png").await?;
+// The keys a run reads on one tick. Everything else it does follows from its
+// seed and these, so a seed and one of these per tick replay a run exactly.
+#[derive(Clone, Copy)]
+struct Input {
+    right: bool,
+    down: bool,
+    jump: bool,
+    jump_pressed: bool,
+}
+impl Input {
+    fn read(keystate: &KeyState) -> Self {
+        Input {
+            right: keystate.is_pressed("ArrowRight"),
+            down: keystate.is_pressed("ArrowDown"),
+            jump: keystate.is_pressed("Space"),
+            jump_pressed: keystate.was_just_pressed("Space"),
+        }
+    }
+}
 
-                let audio = Audio::new()?;
-                let sound = audio.load_sound("SFX_Jump_23.mp3").await?;
-                let background_music = audio.load_sound("background_song.mp3").await?;
-                audio.play_looping_sound(&background_music)?;
+pub struct Walk {
+    world: GameWorld,
+    player: Entity,
+    parallax: Parallax,
+    segments: SegmentLibrary,
+    difficulty: DifficultyCurve,
+    rng: StdRng,
+    timeline: Fixed,
+    last_placement: Placement,
+    ground: Ground,
+    camera: Camera,
+    editor: Editor,
+    editing: bool,
+    mode: Mode,
+    menu: LevelSelect,
+    audio: Audio,
+    pickup_sound: Sound,
+    score: u32,
+    // What the run was generated from and the input it has been given on
+    // every tick so far; together they replay it.
+    seed: u64,
+    inputs: Vec<Input>,
+    // Updates run so far, and a hash of the run as it stood after the last
+    // one, for checking a replay or ghost against tick by tick.
+    tick: u64,
+    state_hash: u64,
+}
+impl Walk {
+    fn new(menu: &LevelSelect, level: Option<&LevelEntry>, seed: u64) -> Result<Self> {
+        let assets = menu.assets();
+        let audio = menu.audio();
+        let parallax = Parallax::new(assets.data::<ParallaxDefinition>("parallax")?, assets)?;
 
-                let rhb = RedHatBoy::new(sheet, engine::load_image("rhb.png").await?, audio, sound);
+        let mut world = GameWorld::new();
+        let player = world.spawn_player(RedHatBoy::new(
+            Rc::new(SpriteSheet::new(
+                assets.sheet("rhb")?,
+                assets.texture("rhb")?,
+            )),
+            Rc::new(assets.data("rhb_hitboxes")?),
+            audio.clone(),
+            assets.sound("jump")?,
+            assets.sound("fall")?,
+        ));
 
-                let tiles = browser::fetch_json("tiles.json").await?;
+        let sprite_sheet = Rc::new(SpriteSheet::new(
+            assets.sheet("tiles")?,
+            assets.texture("tiles")?,
+        ));
 
-                let sprite_sheet = Rc::new(SpriteSheet::new(
-                    tiles.into_serde::<Sheet>()?,
-                    engine::load_image("tiles.png").await?,
-                ));
+        let segments = SegmentLibrary::new(assets, "segments", sprite_sheet.clone())?;
+        let mut ground = Ground::new(sprite_sheet.clone());
+        let editor = Editor::new(assets.data("segments")?, assets, sprite_sheet)?;
+        let start = segments.layouts().start();
+        let last_placement = Placement::first(segments.layouts(), start, Fixed::ZERO);
+        let (timeline, mode) = match level {
+            Some(level) => {
+                let finish = build_course(
+                    &assets.data(&level.course)?,
+                    &segments,
+                    &mut world,
+                    &mut ground,
+                )?;
+                (
+                    finish,
+                    Mode::Course(Course::new(level.name.clone(), finish)),
+                )
+            }
+            None => (
+                segments.spawn(start, Fixed::ZERO, &mut world, &mut ground),
+                Mode::Endless,
+            ),
+        };
 
-                let starting_obstacles =
-                    segments::stone_and_platform(stone.clone(), sprite_sheet.clone(), 0);
-
-                let timeline = rightmost(&starting_obstacles);
-                Ok(Box::new(WalkTheDog::Loaded(Walk {
-                    boy: rhb,
-                    backgrounds: [
-                        Image::new(background.clone(), Point { x: 0, y: 0 }),
-                        Image::new(
-                            background,
-                            Point {
-                                x: background_width,
-                                y: 0,
-                            },
-                        ),
-                    ],
-                    obstacles: starting_obstacles,
-                    obstacle_sheet: sprite_sheet,
-                    stone,
-                    timeline,
-                })))
+        let mut walk = Walk {
+            world,
+            player,
+            parallax,
+            segments,
+            difficulty: assets.data("difficulty")?,
+            rng: StdRng::seed_from_u64(seed),
+            timeline,
+            last_placement,
+            ground,
+            camera: Camera::new(Point {
+                x: Fixed::ZERO,
+                y: GROUND_SHOWN,
+            }),
+            editor,
+            editing: false,
+            mode,
+            menu: menu.clone(),
+            audio,
+            pickup_sound: assets.sound("pickup")?,
+            score: 0,
+            seed,
+            inputs: Vec::new(),
+            tick: 0,
+            state_hash: 0,
+        };
+        walk.lay_ground();
+        Ok(walk)
+    }
+    // Ground is only laid as far as segments have been placed, so every pit
+    // is dug before the ground over it would go down. No more are coming
+    // after a course's finish, so there it just keeps ahead of the camera.
+    fn lay_ground(&mut self) {
+        let right = match self.mode {
+            Mode::Endless => self.timeline,
+            Mode::Course(_) => self.timeline.max(self.camera.left() + TIMELINE_MINIMUM),
+        };
+        self.ground.lay(&mut self.world, right);
+    }
+    // Editing pauses the run; the editor starts out looking at whatever the
+    // game camera was showing.
+    fn toggle_editor(&mut self) {
+        self.editing = !self.editing;
+        if self.editing {
+            self.editor.open(self.camera);
+        }
+    }
+    // A course is over once it is finished or failed; an endless run never is.
+    fn is_over(&self) -> bool {
+        match &self.mode {
+            Mode::Course(course) => course.is_finished() || course.is_failed(),
+            Mode::Endless => false,
+        }
+    }
+    fn boy_position(&self) -> Point {
+        self.world
+            .player(self.player)
+            .map(|boy| boy.position())
+            .unwrap_or_default()
+    }
+    fn distance(&self) -> Fixed {
+        self.boy_position().x
+    }
+    fn collect_pickups(&mut self, from: Point) {
+        for pickup in self.world.resolve_contacts(self.player, from) {
+            self.score += pickup.score;
+            if let (Some(power_up), Some(boy)) =
+                (pickup.power_up, self.world.player_mut(self.player))
+            {
+                boy.power_up(power_up);
+            }
+            if let Err(err) = self.audio.play_sound(&self.pickup_sound) {
+                log!("Error playing pickup sound {:#?}", err);
             }
-            WalkTheDog::Loaded(_) => Err(anyhow!("Error: Game is already initalized!")),
         }
     }
-    fn update(&mut self, keystate: &KeyState) {
-        let mut velocity = Point { x: 0, y: 0 };
-
-        if let WalkTheDog::Loaded(walk) = self {
-            if keystate.is_pressed("ArrowRight") {
-                velocity.x += 3;
-                walk.boy.run_right();
+    fn draw_power_ups(&self, renderer: &Renderer) {
+        let timers: Vec<String> = [
+            (PowerUp::Shield, "Shield"),
+            (PowerUp::Magnet, "Magnet"),
+            (PowerUp::DoubleJump, "Double jump"),
+        ]
+        .iter()
+        .filter_map(|(power_up, name)| {
+            match self
+                .world
+                .player(self.player)
+                .map_or(0, |boy| boy.power_up_remaining(*power_up))
+            {
+                0 => None,
+                ticks => Some(format!(
+                    "{} {}.{}s",
+                    name,
+                    ticks / TICKS_PER_SECOND,
+                    ticks % TICKS_PER_SECOND * 10 / TICKS_PER_SECOND
+                )),
+            }
+        })
+        .collect();
+        if !timers.is_empty() {
+            renderer.collect(renderer.draw_text(
+                &timers.join("  "),
+                &POWER_UP_HUD_POSITION,
+                TEXT_COLOR,
+            ));
+        }
+    }
+    fn draw_course_status(&self, renderer: &Renderer, course: &Course) {
+        let result = if course.is_failed() {
+            "Out".to_string()
+        } else {
+            format!("{}s", course.time())
+        };
+        renderer.collect(renderer.draw_text(
+            &format!("{}  {}", course.name(), result),
+            &Point::from_pixels(420, 30),
+            TEXT_COLOR,
+        ));
+        if course.is_failed() {
+            renderer.collect(renderer.draw_text(
+                "Didn't finish! Press Enter",
+                &Point::from_pixels(180, 200),
+                TEXT_COLOR,
+            ));
+        }
+        if course.is_finished() {
+            renderer.collect(renderer.draw_text(
+                &format!("Finished in {}s! Press Enter", course.time()),
+                &Point::from_pixels(180, 200),
+                TEXT_COLOR,
+            ));
+            renderer.collect(renderer.draw_text(
+                &format!(
+                    "Run {:016x} at tick {} from seed {:016x}",
+                    self.state_hash, self.tick, self.seed
+                ),
+                &Point::from_pixels(180, 225),
+                TEXT_COLOR,
+            ));
+        }
+    }
+    // One fixed tick of the run. It reads no clock and no keys but `input`,
+    // and all its maths is fixed point, so the same seed and inputs give the
+    // same hash on every tick in any browser or native build.
+    fn step(&mut self, input: Input) {
+        self.inputs.push(input);
+        let speed = self.difficulty.speed(self.distance());
+        let from = self.boy_position();
+        if let Some(boy) = self.world.player_mut(self.player) {
+            if input.right {
+                boy.run_right();
             }
 
-            if keystate.is_pressed("ArrowDown") {
-                walk.boy.slide();
+            if input.down {
+                boy.slide();
             }
 
-            if keystate.is_pressed("Space") {
-                walk.boy.jump();
+            // A fresh press while airborne is a double jump; holding Space
+            // keeps jumping again on every landing as before.
+            if input.jump_pressed {
+                boy.double_jump();
+            }
+            if input.jump {
+                boy.jump();
             }
 
-            walk.boy.update();
+            boy.set_running_speed(speed);
+            boy.update();
+        }
+        self.camera.follow(
+            Point {
+                x: self.boy_position().x,
+                y: GROUND_TOP,
+            },
+            FOLLOW_SCREEN_POSITION,
+        );
 
-            let velocity = walk.velocity();
+        let camera_left = self.camera.left();
+        let player = self.player;
+        self.world
+            .retain(|world, entity| entity == player || world.right(entity) > camera_left);
 
-            let [first_background, second_background] = &mut walk.backgrounds;
-            first_background.move_horizontally(velocity / 3);
-            second_background.move_horizontally(velocity / 3);
+        self.world.update_behaviours(self.player);
+        self.world.movement();
+        self.collect_pickups(from);
 
-            if first_background.right() < 0 {
-                first_background.set_x(second_background.right());
+        let distance = self.distance();
+        let out = self
+            .world
+            .player(self.player)
+            .is_some_and(|boy| boy.is_out());
+        match &mut self.mode {
+            Mode::Endless => {
+                if self.timeline < camera_left + TIMELINE_MINIMUM {
+                    self.generate_next_segment();
+                }
             }
-            if second_background.right() < 0 {
-                second_background.set_x(first_background.right());
+            Mode::Course(course) => course.update(distance, input.right, out),
+        }
+        self.lay_ground();
+
+        self.tick += 1;
+        self.state_hash = state_hash(&(self.tick, self.score, self.timeline, &self.world));
+    }
+    fn generate_next_segment(&mut self) {
+        let layouts = self.segments.layouts();
+        let placement = place_next_segment(
+            layouts,
+            &self.difficulty,
+            self.distance(),
+            &self.last_placement,
+            self.timeline,
+            &mut self.rng,
+        )
+        .unwrap_or_else(|| {
+            log!("No clearable segment found, falling back to the start segment");
+            Placement::first(layouts, layouts.start(), self.timeline + FALLBACK_GAP)
+        });
+
+        self.timeline = self.segments.spawn(
+            placement.index,
+            placement.offset,
+            &mut self.world,
+            &mut self.ground,
+        );
+        self.ground.dig(placement.pit);
+        if self.difficulty.spawns_power_up(&mut self.rng) {
+            if let Some(name) = layouts.power_ups().choose(&mut self.rng) {
+                self.segments.spawn_collectible(
+                    name,
+                    Point {
+                        x: placement.offset + POWER_UP_POSITION.x,
+                        y: POWER_UP_POSITION.y,
+                    },
+                    &mut self.world,
+                );
             }
+        }
+        self.last_placement = placement;
+    }
+}
 
-            walk.obstacles.retain(|obstacle| obstacle.right() > 0);
+#[async_trait(?Send)]
+impl Game for WalkTheDog {
+    async fn initalize(&self) -> Result<Box<dyn Game>> {
+        match self {
+            WalkTheDog::Loading(_) => Ok(Box::new(WalkTheDog::Loading(Some(
+                LoadingScreen::start(Audio::new()?),
+            )))),
+            WalkTheDog::Selecting(_) | WalkTheDog::Loaded(_) => {
+                Err(anyhow!("Error: Game is already initalized!"))
+            }
+        }
+    }
+    fn update(&mut self, keystate: &KeyState) {
+        if let WalkTheDog::Loading(Some(screen)) = self {
+            if let Some(menu) = screen.update(keystate) {
+                *self = WalkTheDog::Selecting(menu);
+            }
+        }
 
-            walk.obstacles.iter_mut().for_each(|obstacle| {
-                obstacle.move_horizontally(velocity);
-                obstacle.check_intersection(&mut walk.boy)
-            });
+        if let WalkTheDog::Selecting(menu) = self {
+            if let Some(walk) = menu.update(keystate) {
+                *self = WalkTheDog::Loaded(Box::new(walk));
+            }
+            return;
+        }
 
-            if walk.timeline < TIMELINE_MINIMUM {
-                walk.generate_next_segment();
-            } else {
-                walk.timeline += velocity;
+        if let WalkTheDog::Loaded(walk) = self {
+            if keystate.was_just_pressed("Escape")
+                || (walk.is_over() && keystate.was_just_pressed("Enter"))
+            {
+                *self = WalkTheDog::Selecting(walk.menu.clone());
+                return;
             }
+            if keystate.was_just_pressed("KeyE") {
+                walk.toggle_editor();
+            }
+            if walk.editing {
+                walk.editor.update(keystate);
+                return;
+            }
+
+            walk.step(Input::read(keystate));
         }
     }
     fn draw(&self, renderer: &Renderer) {
-        renderer.clear(&Rect::new_from_x_y(0, 0, 600, HEIGHT));
+        renderer.clear(&Rect::from_pixels(0, 0, 600, HEIGHT.into()));
+
+        if let WalkTheDog::Loading(Some(screen)) = self {
+            screen.draw(renderer);
+        }
+
+        if let WalkTheDog::Selecting(menu) = self {
+            menu.draw(renderer);
+        }
 
         if let WalkTheDog::Loaded(walk) = self {
-            walk.backgrounds.iter().for_each(|bg| bg.draw(renderer));
-            walk.boy.draw(renderer);
-            walk.boy.draw_bounding_box(renderer);
-            walk.obstacles.iter().for_each(|obstacle| {
-                obstacle.draw(renderer);
-                obstacle.draw_bounding_box(renderer);
+            let camera = if walk.editing {
+                walk.editor.camera()
+            } else {
+                walk.camera
+            };
+            renderer.collect(walk.parallax.draw(renderer, &camera));
+            renderer.with_camera(&camera, |renderer| {
+                walk.world.render(renderer);
+                walk.world.render_bounding_boxes(renderer);
+                if let Mode::Course(course) = &walk.mode {
+                    renderer.fill_rect(
+                        &Rect::new_from_x_y(
+                            course.finish(),
+                            Fixed::ZERO,
+                            FINISH_LINE_WIDTH,
+                            HEIGHT.into(),
+                        ),
+                        FINISH_COLOR,
+                    );
+                }
             });
+            if let Mode::Course(course) = &walk.mode {
+                walk.draw_course_status(renderer, course);
+            }
+            if walk.editing {
+                walk.editor.draw(renderer);
+            } else {
+                renderer.collect(renderer.draw_text(
+                    &format!("Score {}", walk.score),
+                    &SCORE_POSITION,
+                    TEXT_COLOR,
+                ));
+                walk.draw_power_ups(renderer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Collider, ColliderKind};
+
+    fn menu() -> LevelSelect {
+        let manifest = serde_json::from_str(include_str!("../static/assets.json")).unwrap();
+        let assets =
+            Assets::headless(manifest, concat!(env!("CARGO_MANIFEST_DIR"), "/static")).unwrap();
+        LevelSelect::new(Rc::new(assets), Audio::headless()).unwrap()
+    }
+
+    // Holds right and jump throughout, jumping again on every landing, with a
+    // fresh press every second for a double jump.
+    fn scripted(ticks: usize) -> Vec<Input> {
+        (0..ticks)
+            .map(|tick| Input {
+                right: true,
+                down: false,
+                jump: true,
+                jump_pressed: tick % 60 == 0,
+            })
+            .collect()
+    }
+
+    fn replay(menu: &LevelSelect, seed: u64, inputs: &[Input]) -> (Walk, Vec<u64>) {
+        let mut walk = Walk::new(menu, None, seed).unwrap();
+        let hashes = inputs
+            .iter()
+            .map(|input| {
+                walk.step(*input);
+                walk.state_hash
+            })
+            .collect();
+        (walk, hashes)
+    }
+
+    #[test]
+    fn a_seeded_run_replays_with_the_same_hash_on_every_tick() {
+        let menu = menu();
+        let (run, hashes) = replay(&menu, 7, &scripted(1200));
+        assert_eq!(run.inputs.len(), 1200);
+
+        let (_, replayed) = replay(&menu, run.seed, &run.inputs);
+        assert_eq!(hashes, replayed);
+
+        let (_, reseeded) = replay(&menu, 8, &run.inputs);
+        assert_ne!(hashes.last(), reseeded.last());
+    }
+
+    #[test]
+    fn dropping_down_a_pit_is_out_and_fails_a_course() {
+        let menu = menu();
+        let level = LevelEntry {
+            name: "Warm Up".to_string(),
+            course: "course_warm_up".to_string(),
+        };
+        let mut walk = Walk::new(&menu, Some(&level), 7).unwrap();
+        // Take the ground laid so far out from under him.
+        let player = walk.player;
+        walk.world.retain(|world, entity| {
+            entity == player
+                || !matches!(
+                    world.collider(entity),
+                    Some(Collider {
+                        kind: ColliderKind::Ground,
+                        ..
+                    })
+                )
+        });
+        let run = Input {
+            right: true,
+            down: false,
+            jump: false,
+            jump_pressed: false,
+        };
+        for _ in 0..100 {
+            if walk.is_over() {
+                break;
+            }
+            walk.step(run);
         }
+        let boy = walk.world.player(player).unwrap();
+        assert!(boy.is_out());
+        assert!(boy.frame_name().starts_with("Hurt"));
+        assert!(matches!(&walk.mode, Mode::Course(course) if course.is_failed()));
     }
 }
@@ -0,0 +1,199 @@
+use crate::engine::Fixed;
+use crate::game::ground::PIT_MIN_WIDTH;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DifficultyCurve {
+    pub base_speed: Fixed,
+    pub max_speed: Fixed,
+    pub ramp_distance: Fixed,
+    pub level_distance: Fixed,
+    pub max_level: u32,
+    #[serde(default)]
+    pub power_up_chance: f64,
+    #[serde(default)]
+    pub pit_chance: f64,
+    #[serde(default)]
+    pub max_pit_width: Fixed,
+}
+
+#[derive(Clone, Copy)]
+pub struct SegmentRating {
+    pub difficulty: u32,
+    pub weight: u32,
+}
+
+impl DifficultyCurve {
+    // Scroll speed climbs linearly from `base_speed` to `max_speed` over the
+    // first `ramp_distance` pixels of the run and stays there.
+    pub fn speed(&self, distance: Fixed) -> Fixed {
+        if distance <= Fixed::ZERO || self.ramp_distance <= Fixed::ZERO {
+            return self.base_speed;
+        }
+        if distance >= self.ramp_distance {
+            return self.max_speed;
+        }
+        self.base_speed + (self.max_speed - self.base_speed) * distance / self.ramp_distance
+    }
+
+    pub fn level(&self, distance: Fixed) -> u32 {
+        if distance <= Fixed::ZERO || self.level_distance <= Fixed::ZERO {
+            return 0;
+        }
+        ((distance / self.level_distance).floor() as u32).min(self.max_level)
+    }
+
+    // Segments rated above the current level are locked; unlocked ones get
+    // heavier the harder they are, so later levels lean on harder segments
+    // without ever dropping the easy ones entirely.
+    pub fn weight(&self, level: u32, rating: &SegmentRating) -> u32 {
+        if rating.difficulty > level {
+            0
+        } else {
+            rating.weight * (1 + rating.difficulty * level)
+        }
+    }
+
+    // Rolled once per generated segment. Power-ups are a bonus on top of a
+    // layout that is already clearable, never something it relies on.
+    pub fn spawns_power_up(&self, rng: &mut impl Rng) -> bool {
+        self.power_up_chance > 0.0 && rng.gen_bool(self.power_up_chance.min(1.0))
+    }
+
+    // Also rolled once per generated segment: how wide a pit to dig into the
+    // seam in front of it, if any. Whether it can be jumped at the speed the
+    // boy will be going is for the reachability checker to decide.
+    pub fn seam_pit(&self, rng: &mut impl Rng) -> Option<Fixed> {
+        if self.pit_chance <= 0.0
+            || self.max_pit_width < PIT_MIN_WIDTH
+            || !rng.gen_bool(self.pit_chance.min(1.0))
+        {
+            return None;
+        }
+        Some(Fixed::from_int(rng.gen_range(
+            PIT_MIN_WIDTH.floor()..=self.max_pit_width.floor(),
+        )))
+    }
+
+    pub fn choose_segment(
+        &self,
+        distance: Fixed,
+        ratings: &[SegmentRating],
+        rng: &mut impl Rng,
+    ) -> usize {
+        let level = self.level(distance);
+        let weights = ratings.iter().map(|rating| self.weight(level, rating));
+        match WeightedIndex::new(weights) {
+            Ok(distribution) => distribution.sample(rng),
+            Err(_) => rng.gen_range(0..ratings.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn curve() -> DifficultyCurve {
+        DifficultyCurve {
+            base_speed: Fixed::from_int(4),
+            max_speed: Fixed::from_int(10),
+            ramp_distance: Fixed::from_int(6000),
+            level_distance: Fixed::from_int(1000),
+            max_level: 3,
+            power_up_chance: 0.0,
+            pit_chance: 0.0,
+            max_pit_width: Fixed::ZERO,
+        }
+    }
+
+    fn ratings() -> Vec<SegmentRating> {
+        vec![
+            SegmentRating {
+                difficulty: 0,
+                weight: 1,
+            },
+            SegmentRating {
+                difficulty: 2,
+                weight: 1,
+            },
+        ]
+    }
+
+    fn picks(distance: Fixed, seed: u64) -> [u32; 2] {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut counts = [0; 2];
+        for _ in 0..1000 {
+            counts[curve().choose_segment(distance, &ratings(), &mut rng)] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn speed_ramps_from_base_to_max() {
+        let curve = curve();
+        assert_eq!(curve.speed(Fixed::ZERO), Fixed::from_int(4));
+        assert_eq!(curve.speed(Fixed::from_int(3000)), Fixed::from_int(7));
+        assert_eq!(curve.speed(Fixed::from_int(6000)), Fixed::from_int(10));
+        assert_eq!(curve.speed(Fixed::from_int(60000)), Fixed::from_int(10));
+    }
+
+    #[test]
+    fn level_rises_with_distance_up_to_max() {
+        let curve = curve();
+        assert_eq!(curve.level(Fixed::from_int(999)), 0);
+        assert_eq!(curve.level(Fixed::from_int(2500)), 2);
+        assert_eq!(curve.level(Fixed::from_int(100000)), 3);
+    }
+
+    #[test]
+    fn hard_segments_are_locked_early() {
+        assert_eq!(picks(Fixed::ZERO, 7), [1000, 0]);
+    }
+
+    #[test]
+    fn hard_segments_dominate_late() {
+        let [easy, hard] = picks(Fixed::from_int(5000), 7);
+        assert!(hard > easy * 3, "easy {} hard {}", easy, hard);
+    }
+
+    #[test]
+    fn seams_are_dug_at_the_pit_chance_and_no_narrower_than_a_pit_can_be() {
+        let curve = DifficultyCurve {
+            pit_chance: 0.2,
+            max_pit_width: Fixed::from_int(320),
+            ..curve()
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        let pits: Vec<Fixed> = (0..10000)
+            .filter_map(|_| curve.seam_pit(&mut rng))
+            .collect();
+        assert!((1800..2200).contains(&pits.len()), "{} pits", pits.len());
+        assert!(pits
+            .iter()
+            .all(|width| (PIT_MIN_WIDTH..=curve.max_pit_width).contains(width)));
+    }
+
+    #[test]
+    fn no_pits_without_a_chance_or_room_for_one() {
+        let mut rng = StdRng::seed_from_u64(7);
+        assert!((0..1000).all(|_| curve().seam_pit(&mut rng).is_none()));
+        let narrow = DifficultyCurve {
+            pit_chance: 1.0,
+            max_pit_width: PIT_MIN_WIDTH - Fixed::from_int(1),
+            ..curve()
+        };
+        assert!((0..1000).all(|_| narrow.seam_pit(&mut rng).is_none()));
+    }
+
+    #[test]
+    fn selection_is_reproducible_from_a_seed() {
+        let distance = Fixed::from_int(2000);
+        assert_eq!(picks(distance, 42), picks(distance, 42));
+    }
+}
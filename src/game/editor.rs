@@ -0,0 +1,606 @@
+use super::{HEIGHT, TEXT_COLOR};
+use crate::browser;
+use crate::engine::{Assets, Camera, Fixed, KeyState, Point, Rect, Renderer, SpriteSheet, Texture};
+use crate::game::enemies::{spawn_enemy, EnemyKind};
+use crate::game::ground::{GROUND_TOP, PIT_MIN_WIDTH, TILE_SIZE};
+use crate::game::obstacles::{
+    spawn_barrier, spawn_collectible, spawn_overhead, spawn_platform, Pickup, PlatformMotion,
+};
+use crate::game::segments::{
+    arc_positions, CollectibleDefinition, ObstacleDefinition, PlatformDefinition,
+    SegmentDefinition, SegmentLibraryDefinition,
+};
+use crate::game::world::GameWorld;
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+const GRID: Fixed = Fixed::from_int(10);
+const GRID_LINE_SPACING: Fixed = Fixed::from_int(50);
+const GRID_COLOR: &str = "rgba(0, 0, 0, 0.15)";
+const SELECTED_COLOR: &str = "#0080FF";
+const ACTIVE_BOX_COLOR: &str = "#00C000";
+const PIT_COLOR: &str = "rgba(0, 0, 0, 0.5)";
+const PAN_SPEED: Fixed = Fixed::from_int(10);
+const EXPORTED_NAME: &str = "edited";
+const BOB_HEIGHT: Fixed = Fixed::from_int(80);
+const BOB_PERIOD: Fixed = Fixed::from_int(500);
+const HELP: [&str; 4] = [
+    "Click place/select, Delete remove, A/D pan",
+    "B next box, arrows resize, shift+arrows move",
+    "M cycles still, bobbing, crumbling, overhead or rolling",
+    "X export to console, E back to the game",
+];
+
+const ENEMIES: [(EnemyKind, &str); 2] = [(EnemyKind::Dog, "dog"), (EnemyKind::Bird, "bird")];
+// Enemies are placed with their feet on the floor; a bird hovers at the
+// height that catches a running boy but not a sliding one.
+const DOG_Y: Fixed = Fixed::from_int(544);
+const BIRD_Y: Fixed = Fixed::from_int(470);
+
+enum Tool {
+    Platform(String),
+    Barrier(String),
+    Collectible(String),
+    Enemy(EnemyKind, &'static str),
+    Pit,
+}
+impl Tool {
+    fn name(&self) -> &str {
+        match self {
+            Tool::Platform(name) | Tool::Barrier(name) | Tool::Collectible(name) => name,
+            Tool::Enemy(_, name) => name,
+            Tool::Pit => "pit",
+        }
+    }
+}
+
+// Lays out a new segment on top of the paused run. Obstacles are kept as
+// segment definitions, x relative to where the editor was first opened, so
+// exporting is just serializing them.
+pub struct Editor {
+    sprite_sheet: Rc<SpriteSheet>,
+    enemy_sheet: Rc<SpriteSheet>,
+    textures: HashMap<String, Texture>,
+    platforms: BTreeMap<String, PlatformDefinition>,
+    collectibles: BTreeMap<String, CollectibleDefinition>,
+    tools: Vec<Tool>,
+    tool: usize,
+    camera: Camera,
+    origin: Option<Fixed>,
+    obstacles: Vec<ObstacleDefinition>,
+    selected: Option<usize>,
+    active_box: usize,
+    cursor: Point,
+}
+impl Editor {
+    pub fn new(
+        definition: SegmentLibraryDefinition,
+        assets: &Assets,
+        sprite_sheet: Rc<SpriteSheet>,
+    ) -> Result<Self> {
+        let mut textures = HashMap::new();
+        for segment in &definition.segments {
+            for obstacle in &segment.obstacles {
+                if let ObstacleDefinition::Barrier { image, .. } = obstacle {
+                    textures.insert(image.clone(), assets.texture(image)?);
+                }
+            }
+        }
+        let platforms: BTreeMap<String, PlatformDefinition> =
+            definition.platforms.into_iter().collect();
+        let collectibles: BTreeMap<String, CollectibleDefinition> =
+            definition.collectibles.into_iter().collect();
+
+        let mut images: Vec<String> = textures.keys().cloned().collect();
+        images.sort();
+        let tools: Vec<Tool> = platforms
+            .keys()
+            .cloned()
+            .map(Tool::Platform)
+            .chain(images.into_iter().map(Tool::Barrier))
+            .chain(collectibles.keys().cloned().map(Tool::Collectible))
+            .chain(ENEMIES.iter().map(|(kind, name)| Tool::Enemy(*kind, name)))
+            .chain(std::iter::once(Tool::Pit))
+            .collect();
+        Ok(Editor {
+            sprite_sheet,
+            enemy_sheet: Rc::new(SpriteSheet::new(
+                assets.sheet("enemies")?,
+                assets.texture("enemies")?,
+            )),
+            textures,
+            platforms,
+            collectibles,
+            tools,
+            tool: 0,
+            camera: Camera::default(),
+            origin: None,
+            obstacles: Vec::new(),
+            selected: None,
+            active_box: 0,
+            cursor: Point::default(),
+        })
+    }
+
+    pub fn open(&mut self, camera: Camera) {
+        self.camera = camera;
+        self.origin.get_or_insert(camera.left());
+    }
+
+    pub fn camera(&self) -> Camera {
+        self.camera
+    }
+
+    fn origin(&self) -> Fixed {
+        self.origin.unwrap_or_default()
+    }
+
+    pub fn update(&mut self, keystate: &KeyState) {
+        if keystate.is_pressed("KeyA") {
+            self.camera.position.x -= PAN_SPEED;
+        }
+        if keystate.is_pressed("KeyD") {
+            self.camera.position.x += PAN_SPEED;
+        }
+
+        let mouse = self.camera.to_world(keystate.mouse_position());
+        self.cursor = Point {
+            x: snap(mouse.x),
+            y: snap(mouse.y),
+        };
+
+        if keystate.was_just_pressed("KeyT") {
+            self.tool = (self.tool + 1) % self.tools.len();
+        }
+        if keystate.was_mouse_just_pressed() {
+            self.selected = self.obstacle_at(mouse).or_else(|| {
+                self.obstacles.push(self.tool_obstacle());
+                Some(self.obstacles.len() - 1)
+            });
+            self.active_box = 0;
+        }
+        if keystate.was_just_pressed("Delete") || keystate.was_just_pressed("Backspace") {
+            if let Some(index) = self.selected.take() {
+                self.obstacles.remove(index);
+            }
+        }
+        if keystate.was_just_pressed("KeyB") {
+            self.active_box += 1;
+        }
+        if keystate.was_just_pressed("KeyM") {
+            self.cycle_motion();
+        }
+        self.edit_bounding_box(keystate);
+
+        if keystate.was_just_pressed("KeyX") {
+            self.export();
+        }
+    }
+
+    // Hanging a platform up as an overhead is the last step of the cycle,
+    // after which it comes back down as a still platform.
+    fn cycle_motion(&mut self) {
+        let obstacle = match self
+            .selected
+            .and_then(|index| self.obstacles.get_mut(index))
+        {
+            Some(obstacle) => obstacle,
+            None => return,
+        };
+        match obstacle {
+            ObstacleDefinition::Platform {
+                platform,
+                x,
+                y,
+                motion: PlatformMotion::Crumble,
+            } => {
+                *obstacle = ObstacleDefinition::Overhead {
+                    platform: platform.clone(),
+                    x: *x,
+                    y: *y,
+                }
+            }
+            ObstacleDefinition::Platform { motion, .. } => {
+                *motion = match motion {
+                    PlatformMotion::Still => PlatformMotion::Bob {
+                        height: BOB_HEIGHT,
+                        period: BOB_PERIOD,
+                    },
+                    _ => PlatformMotion::Crumble,
+                }
+            }
+            ObstacleDefinition::Overhead { platform, x, y } => {
+                *obstacle = ObstacleDefinition::Platform {
+                    platform: platform.clone(),
+                    x: *x,
+                    y: *y,
+                    motion: PlatformMotion::Still,
+                }
+            }
+            ObstacleDefinition::Barrier { rolling, .. } => *rolling = !*rolling,
+            _ => {}
+        }
+    }
+
+    // Bounding boxes belong to the platform definition, so resizing one
+    // changes every copy of that platform in the segment and in the export.
+    // A pit is its own box and only gets wider or narrower.
+    fn edit_bounding_box(&mut self, keystate: &KeyState) {
+        let platform = match self
+            .selected
+            .and_then(|index| self.obstacles.get_mut(index))
+        {
+            Some(
+                ObstacleDefinition::Platform { platform, .. }
+                | ObstacleDefinition::Overhead { platform, .. },
+            ) => platform.clone(),
+            Some(ObstacleDefinition::Pit { width, .. }) => {
+                if keystate.was_just_pressed("ArrowLeft") {
+                    *width = (*width - GRID).max(PIT_MIN_WIDTH);
+                }
+                if keystate.was_just_pressed("ArrowRight") {
+                    *width += GRID;
+                }
+                return;
+            }
+            _ => return,
+        };
+        let definition = match self.platforms.get_mut(&platform) {
+            Some(definition) if !definition.bounding_boxes.is_empty() => definition,
+            _ => return,
+        };
+        self.active_box %= definition.bounding_boxes.len();
+        let bounding_box = &mut definition.bounding_boxes[self.active_box];
+
+        let moving = keystate.is_pressed("ShiftLeft") || keystate.is_pressed("ShiftRight");
+        for (code, x, y) in [
+            ("ArrowLeft", -1, 0),
+            ("ArrowRight", 1, 0),
+            ("ArrowUp", 0, -1),
+            ("ArrowDown", 0, 1),
+        ] {
+            if !keystate.was_just_pressed(code) {
+                continue;
+            }
+            if moving {
+                bounding_box.position.x += GRID * x;
+                bounding_box.position.y += GRID * y;
+            } else {
+                bounding_box.width = (bounding_box.width + GRID * x).max(GRID);
+                bounding_box.height = (bounding_box.height + GRID * y).max(GRID);
+            }
+        }
+    }
+
+    fn tool_obstacle(&self) -> ObstacleDefinition {
+        let x = self.cursor.x - self.origin();
+        let y = self.cursor.y;
+        match &self.tools[self.tool] {
+            Tool::Platform(platform) => ObstacleDefinition::Platform {
+                platform: platform.clone(),
+                x,
+                y,
+                motion: PlatformMotion::Still,
+            },
+            Tool::Barrier(image) => ObstacleDefinition::Barrier {
+                image: image.clone(),
+                x,
+                y,
+                rolling: false,
+            },
+            Tool::Collectible(collectible) => ObstacleDefinition::Collectible {
+                collectible: collectible.clone(),
+                x,
+                y,
+            },
+            Tool::Enemy(enemy, _) => ObstacleDefinition::Enemy {
+                enemy: *enemy,
+                x,
+                y: match enemy {
+                    EnemyKind::Dog => DOG_Y,
+                    EnemyKind::Bird => BIRD_Y,
+                },
+            },
+            Tool::Pit => ObstacleDefinition::Pit {
+                x,
+                width: PIT_MIN_WIDTH,
+            },
+        }
+    }
+
+    fn obstacle_at(&self, point: Point) -> Option<usize> {
+        self.obstacles
+            .iter()
+            .rposition(|obstacle| self.bounds(obstacle).contains(point))
+    }
+
+    fn bounds(&self, obstacle: &ObstacleDefinition) -> Rect {
+        match obstacle {
+            ObstacleDefinition::Barrier { image, x, y, .. } => match self.textures.get(image) {
+                Some(texture) => Rect::new_from_x_y(
+                    self.origin() + *x,
+                    *y,
+                    texture.width().into(),
+                    texture.height().into(),
+                ),
+                None => Rect::default(),
+            },
+            ObstacleDefinition::Platform { platform, x, y, .. }
+            | ObstacleDefinition::Overhead { platform, x, y } => {
+                let boxes = self
+                    .platforms
+                    .get(platform)
+                    .map(|definition| definition.bounding_boxes.as_slice())
+                    .unwrap_or_default();
+                let left = boxes.iter().map(|b| b.x()).min().unwrap_or_default();
+                let top = boxes.iter().map(|b| b.y()).min().unwrap_or_default();
+                let right = boxes.iter().map(|b| b.right()).max().unwrap_or_default();
+                let bottom = boxes.iter().map(|b| b.bottom()).max().unwrap_or_default();
+                Rect::new_from_x_y(
+                    self.origin() + *x + left,
+                    *y + top,
+                    right - left,
+                    bottom - top,
+                )
+            }
+            ObstacleDefinition::Collectible { collectible, x, y } => {
+                let (width, height) = self.collectible_size(collectible);
+                Rect::new_from_x_y(self.origin() + *x, *y, width, height)
+            }
+            ObstacleDefinition::Enemy { enemy, x, y } => enemy.hitbox(Point {
+                x: self.origin() + *x,
+                y: *y,
+            }),
+            ObstacleDefinition::Pit { x, width } => {
+                Rect::new_from_x_y(self.origin() + *x, GROUND_TOP, *width, TILE_SIZE)
+            }
+            ObstacleDefinition::CollectibleArc {
+                collectible,
+                x,
+                y,
+                width,
+                height,
+                ..
+            } => {
+                let (sprite_width, sprite_height) = self.collectible_size(collectible);
+                Rect::new_from_x_y(
+                    self.origin() + *x,
+                    *y - *height,
+                    *width + sprite_width,
+                    *height + sprite_height,
+                )
+            }
+        }
+    }
+
+    fn collectible_size(&self, collectible: &str) -> (Fixed, Fixed) {
+        self.collectibles
+            .get(collectible)
+            .and_then(|definition| self.sprite_sheet.cell(&definition.sprite))
+            .map(|cell| (cell.frame.w.into(), cell.frame.h.into()))
+            .unwrap_or_default()
+    }
+
+    // Spawns the same obstacles the segment library would, so what is drawn
+    // here is exactly what the run will spawn.
+    fn spawn(&self, obstacle: &ObstacleDefinition, world: &mut GameWorld) {
+        let origin = self.origin();
+        match obstacle {
+            ObstacleDefinition::Barrier {
+                image,
+                x,
+                y,
+                rolling,
+            } => {
+                if let Some(texture) = self.textures.get(image) {
+                    spawn_barrier(
+                        world,
+                        texture,
+                        Point {
+                            x: origin + *x,
+                            y: *y,
+                        },
+                        *rolling,
+                    );
+                }
+            }
+            ObstacleDefinition::Platform {
+                platform,
+                x,
+                y,
+                motion,
+            } => {
+                if let Some(definition) = self.platforms.get(platform) {
+                    spawn_platform(
+                        world,
+                        &self.sprite_sheet,
+                        Point {
+                            x: origin + *x,
+                            y: *y,
+                        },
+                        &definition.sprites,
+                        &definition.bounding_boxes,
+                        *motion,
+                    );
+                }
+            }
+            ObstacleDefinition::Overhead { platform, x, y } => {
+                if let Some(definition) = self.platforms.get(platform) {
+                    spawn_overhead(
+                        world,
+                        &self.sprite_sheet,
+                        Point {
+                            x: origin + *x,
+                            y: *y,
+                        },
+                        &definition.sprites,
+                        &definition.bounding_boxes,
+                    );
+                }
+            }
+            ObstacleDefinition::Collectible { collectible, x, y } => self.spawn_collectibles(
+                world,
+                collectible,
+                vec![Point {
+                    x: origin + *x,
+                    y: *y,
+                }],
+            ),
+            ObstacleDefinition::Enemy { enemy, x, y } => {
+                spawn_enemy(
+                    world,
+                    &self.enemy_sheet,
+                    *enemy,
+                    Point {
+                        x: origin + *x,
+                        y: *y,
+                    },
+                );
+            }
+            ObstacleDefinition::CollectibleArc {
+                collectible,
+                x,
+                y,
+                count,
+                width,
+                height,
+            } => self.spawn_collectibles(
+                world,
+                collectible,
+                arc_positions(origin + *x, *y, *count, *width, *height),
+            ),
+            // The ground under the paused run stays put; `draw` marks the
+            // pit over it instead.
+            ObstacleDefinition::Pit { .. } => {}
+        }
+    }
+
+    fn spawn_collectibles(&self, world: &mut GameWorld, collectible: &str, positions: Vec<Point>) {
+        if let Some(definition) = self.collectibles.get(collectible) {
+            for position in positions {
+                spawn_collectible(
+                    world,
+                    &self.sprite_sheet,
+                    position,
+                    &definition.sprite,
+                    Pickup {
+                        score: definition.score,
+                        power_up: definition.power_up,
+                    },
+                );
+            }
+        }
+    }
+
+    fn export(&self) {
+        let definition = SegmentLibraryDefinition {
+            start: EXPORTED_NAME.to_string(),
+            platforms: self.platforms.clone().into_iter().collect(),
+            collectibles: self.collectibles.clone().into_iter().collect(),
+            segments: vec![SegmentDefinition {
+                name: EXPORTED_NAME.to_string(),
+                difficulty: 0,
+                weight: 1,
+                obstacles: self.obstacles.clone(),
+            }],
+        };
+        match browser::to_pretty_json(&definition) {
+            Ok(json) => {
+                log!("Exported segment:\n{}", json);
+            }
+            Err(err) => {
+                log!("Could not export segment {:#?}", err);
+            }
+        }
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        renderer.with_camera(&self.camera, |renderer| {
+            self.draw_grid(renderer);
+            let mut world = GameWorld::new();
+            for obstacle in &self.obstacles {
+                self.spawn(obstacle, &mut world);
+                self.draw_pit(renderer, obstacle);
+            }
+            world.render(renderer);
+            world.render_bounding_boxes(renderer);
+            if let Some(obstacle) = self.selected.and_then(|index| self.obstacles.get(index)) {
+                renderer.outline_rect(&self.bounds(obstacle), SELECTED_COLOR);
+                self.draw_active_box(renderer, obstacle);
+            }
+
+            let mut ghost = GameWorld::new();
+            self.spawn(&self.tool_obstacle(), &mut ghost);
+            self.draw_pit(renderer, &self.tool_obstacle());
+            ghost.render(renderer);
+        });
+
+        let mut position = Point::from_pixels(10, 20);
+        renderer.collect(renderer.draw_text(
+            &format!("Editing, tool {} (T)", self.tools[self.tool].name()),
+            &position,
+            TEXT_COLOR,
+        ));
+        for line in HELP {
+            position.y += Fixed::from_int(20);
+            renderer.collect(renderer.draw_text(line, &position, TEXT_COLOR));
+        }
+    }
+
+    fn draw_grid(&self, renderer: &Renderer) {
+        let left = self.camera.left();
+        let right = left + self.camera.world_width(renderer.viewport_width());
+        let height: Fixed = HEIGHT.into();
+
+        let mut x = left - left.rem_euclid(GRID_LINE_SPACING);
+        while x < right {
+            renderer.fill_rect(
+                &Rect::new_from_x_y(x, Fixed::ZERO, Fixed::from_int(1), height),
+                GRID_COLOR,
+            );
+            x += GRID_LINE_SPACING;
+        }
+        let mut y = Fixed::ZERO;
+        while y < height {
+            renderer.fill_rect(
+                &Rect::new_from_x_y(left, y, right - left, Fixed::from_int(1)),
+                GRID_COLOR,
+            );
+            y += GRID_LINE_SPACING;
+        }
+    }
+
+    fn draw_pit(&self, renderer: &Renderer, obstacle: &ObstacleDefinition) {
+        if let ObstacleDefinition::Pit { .. } = obstacle {
+            renderer.fill_rect(&self.bounds(obstacle), PIT_COLOR);
+        }
+    }
+
+    fn draw_active_box(&self, renderer: &Renderer, obstacle: &ObstacleDefinition) {
+        if let ObstacleDefinition::Platform { platform, x, y, .. }
+        | ObstacleDefinition::Overhead { platform, x, y } = obstacle
+        {
+            let boxes = match self.platforms.get(platform) {
+                Some(definition) if !definition.bounding_boxes.is_empty() => {
+                    &definition.bounding_boxes
+                }
+                _ => return,
+            };
+            let bounding_box = boxes[self.active_box % boxes.len()];
+            renderer.outline_rect(
+                &Rect::new_from_x_y(
+                    self.origin() + *x + bounding_box.x(),
+                    *y + bounding_box.y(),
+                    bounding_box.width,
+                    bounding_box.height,
+                ),
+                ACTIVE_BOX_COLOR,
+            );
+        }
+    }
+}
+
+fn snap(value: Fixed) -> Fixed {
+    value - value.rem_euclid(GRID)
+}
@@ -0,0 +1,681 @@
+use self::enemy_states::*;
+use super::obstacles::start_of_tick;
+use super::world::{Behaviour, GameWorld};
+use crate::engine::{
+    collide, Collider, ColliderKind, Entity, Fixed, Point, Rect, Sprite, SpriteSheet,
+};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EnemyKind {
+    Dog,
+    Bird,
+}
+
+// How each kind moves and where it can be hit. A bird flies just low enough
+// to catch a running boy and just high enough for a sliding one to pass.
+struct EnemyProfile {
+    name: &'static str,
+    hitbox: Rect,
+    patrol: Fixed,
+    patrol_period: Fixed,
+    sight: Fixed,
+    chase_divisor: i32,
+    chase_limit: Fixed,
+}
+
+const DOG: EnemyProfile = EnemyProfile {
+    name: "Dog",
+    hitbox: Rect::from_pixels(6, 10, 68, 44),
+    patrol: Fixed::from_int(60),
+    patrol_period: Fixed::from_int(400),
+    sight: Fixed::from_int(450),
+    chase_divisor: 2,
+    chase_limit: Fixed::from_int(200),
+};
+const BIRD: EnemyProfile = EnemyProfile {
+    name: "Bird",
+    hitbox: Rect::from_pixels(6, 6, 52, 30),
+    patrol: Fixed::from_int(40),
+    patrol_period: Fixed::from_int(300),
+    sight: Fixed::from_int(500),
+    chase_divisor: 3,
+    chase_limit: Fixed::from_int(160),
+};
+
+impl EnemyKind {
+    fn profile(self) -> &'static EnemyProfile {
+        match self {
+            EnemyKind::Dog => &DOG,
+            EnemyKind::Bird => &BIRD,
+        }
+    }
+
+    // The furthest an enemy strays left of where it was placed.
+    pub fn reach(self) -> Fixed {
+        let profile = self.profile();
+        profile.patrol + profile.chase_limit
+    }
+
+    pub fn hitbox(self, position: Point) -> Rect {
+        let hitbox = self.profile().hitbox;
+        Rect::new_from_x_y(
+            position.x + hitbox.x(),
+            position.y + hitbox.y(),
+            hitbox.width,
+            hitbox.height,
+        )
+    }
+}
+
+// Like the moving obstacles, enemies follow how far the boy has run: they
+// pace around `home` until he comes into sight, then charge at a fraction of
+// his speed. Being a function of his position alone, the reachability
+// checker replays it exactly.
+pub fn enemy_x(kind: EnemyKind, home: Fixed, distance: Fixed) -> Fixed {
+    let profile = kind.profile();
+    let spotted = home - profile.sight;
+    let patrol = |distance: Fixed| {
+        let along = distance.rem_euclid(profile.patrol_period) / profile.patrol_period;
+        profile.patrol * ((along * 2 - Fixed::from_int(1)).abs() * 2 - Fixed::from_int(1))
+    };
+    if distance < spotted {
+        home + patrol(distance)
+    } else {
+        let charged = ((distance - spotted) / profile.chase_divisor).min(profile.chase_limit);
+        home + patrol(spotted) - charged
+    }
+}
+
+fn is_spotted(kind: EnemyKind, home: Fixed, distance: Fixed) -> bool {
+    distance >= home - kind.profile().sight
+}
+
+pub enum EnemyContact {
+    None,
+    Stomp,
+    Tumble,
+    KnockOut,
+}
+
+// Landing on the top half of an enemy squashes it and sliding into one
+// bowls it over; any other touch knocks the boy out. Like the other contacts
+// his boxes are swept along `motion`, and whether he came down on top is
+// judged by where his feet were when the tick began, so it doesn't matter
+// how far he fell this tick.
+pub fn enemy_contact(
+    boy_boxes: &[Rect],
+    motion: Point,
+    sliding: bool,
+    enemy_box: &Rect,
+) -> EnemyContact {
+    let started: Vec<Rect> = boy_boxes
+        .iter()
+        .map(|hitbox| start_of_tick(hitbox, motion))
+        .collect();
+    let feet = started
+        .iter()
+        .map(Rect::bottom)
+        .max()
+        .unwrap_or(Fixed::ZERO);
+    if !started
+        .iter()
+        .any(|hitbox| collide(hitbox, motion, enemy_box).is_some())
+    {
+        EnemyContact::None
+    } else if motion.y > Fixed::ZERO && feet <= enemy_box.y() + enemy_box.height / 2 {
+        EnemyContact::Stomp
+    } else if sliding {
+        EnemyContact::Tumble
+    } else {
+        EnemyContact::KnockOut
+    }
+}
+
+#[derive(Hash)]
+pub struct Enemy {
+    state: EnemyStateMachine,
+}
+impl Enemy {
+    fn frame_name(&self) -> String {
+        let context = self.state.context();
+        format!(
+            "{} {} ({}).png",
+            context.kind.profile().name,
+            self.state.frame_name(),
+            context.frame / FRAME_TICKS % self.state.frame_count() + 1
+        )
+    }
+
+    // Once it has been dealt with an enemy loses its collider, and it leaves
+    // the world altogether when it is gone.
+    pub fn update(&mut self, entity: Entity, world: &mut GameWorld, distance: Fixed) {
+        self.state = self.state.clone().transition(Event::Update(distance));
+        if let EnemyStateMachine::Gone(_) = self.state {
+            world.despawn(entity);
+            return;
+        }
+        world.set_position(entity, self.state.context().position);
+        if let Some(Sprite::Frame { name, .. }) = world.sprites.get_mut(&entity) {
+            *name = self.frame_name();
+        }
+        if !self.state.is_dangerous() {
+            world.remove_collider(entity);
+        }
+    }
+
+    pub fn stomp(&mut self) {
+        self.state = self.state.clone().transition(Event::Stomp);
+    }
+
+    pub fn tumble(&mut self) {
+        self.state = self.state.clone().transition(Event::Tumble);
+    }
+}
+
+pub fn spawn_enemy(
+    world: &mut GameWorld,
+    sheet: &Rc<SpriteSheet>,
+    kind: EnemyKind,
+    home: Point,
+) -> Entity {
+    let enemy = Enemy {
+        state: EnemyStateMachine::Patrolling(EnemyState::new(kind, home)),
+    };
+    let entity = world.spawn(home);
+    world.sprites.insert(
+        entity,
+        Sprite::Frame {
+            sheet: sheet.clone(),
+            name: enemy.frame_name(),
+        },
+    );
+    world.set_collider(
+        entity,
+        Collider {
+            kind: ColliderKind::Sensor,
+            bounding_boxes: vec![kind.hitbox(Point::default())],
+        },
+    );
+    world.behaviours.insert(entity, Behaviour::Enemy(enemy));
+    entity
+}
+
+#[derive(Clone, Hash)]
+enum EnemyStateMachine {
+    Patrolling(EnemyState<Patrolling>),
+    Chasing(EnemyState<Chasing>),
+    Stomped(EnemyState<Stomped>),
+    Tumbling(EnemyState<Tumbling>),
+    Gone(EnemyState<Gone>),
+}
+
+pub enum Event {
+    Update(Fixed),
+    Stomp,
+    Tumble,
+}
+
+impl EnemyStateMachine {
+    fn transition(self, event: Event) -> Self {
+        match (self.clone(), event) {
+            (EnemyStateMachine::Patrolling(state), Event::Update(distance)) => {
+                state.update(distance).into()
+            }
+            (EnemyStateMachine::Chasing(state), Event::Update(distance)) => {
+                state.update(distance).into()
+            }
+            (EnemyStateMachine::Stomped(state), Event::Update(_)) => state.update().into(),
+            (EnemyStateMachine::Tumbling(state), Event::Update(_)) => state.update().into(),
+
+            (EnemyStateMachine::Patrolling(state), Event::Stomp) => state.stomp().into(),
+            (EnemyStateMachine::Chasing(state), Event::Stomp) => state.stomp().into(),
+
+            (EnemyStateMachine::Patrolling(state), Event::Tumble) => state.tumble().into(),
+            (EnemyStateMachine::Chasing(state), Event::Tumble) => state.tumble().into(),
+            _ => self,
+        }
+    }
+
+    fn frame_name(&self) -> &str {
+        match self {
+            EnemyStateMachine::Patrolling(state) => state.frame_name(),
+            EnemyStateMachine::Chasing(state) => state.frame_name(),
+            EnemyStateMachine::Stomped(state) => state.frame_name(),
+            EnemyStateMachine::Tumbling(state) => state.frame_name(),
+            EnemyStateMachine::Gone(state) => state.frame_name(),
+        }
+    }
+
+    fn frame_count(&self) -> u32 {
+        match self {
+            EnemyStateMachine::Patrolling(_) | EnemyStateMachine::Chasing(_) => WALKING_FRAMES,
+            _ => 1,
+        }
+    }
+
+    fn context(&self) -> &EnemyContext {
+        match self {
+            EnemyStateMachine::Patrolling(state) => state.context(),
+            EnemyStateMachine::Chasing(state) => state.context(),
+            EnemyStateMachine::Stomped(state) => state.context(),
+            EnemyStateMachine::Tumbling(state) => state.context(),
+            EnemyStateMachine::Gone(state) => state.context(),
+        }
+    }
+
+    fn is_dangerous(&self) -> bool {
+        matches!(
+            self,
+            EnemyStateMachine::Patrolling(_) | EnemyStateMachine::Chasing(_)
+        )
+    }
+}
+
+impl From<EnemyState<Patrolling>> for EnemyStateMachine {
+    fn from(state: EnemyState<Patrolling>) -> Self {
+        EnemyStateMachine::Patrolling(state)
+    }
+}
+
+impl From<EnemyState<Chasing>> for EnemyStateMachine {
+    fn from(state: EnemyState<Chasing>) -> Self {
+        EnemyStateMachine::Chasing(state)
+    }
+}
+
+impl From<EnemyState<Stomped>> for EnemyStateMachine {
+    fn from(state: EnemyState<Stomped>) -> Self {
+        EnemyStateMachine::Stomped(state)
+    }
+}
+
+impl From<EnemyState<Tumbling>> for EnemyStateMachine {
+    fn from(state: EnemyState<Tumbling>) -> Self {
+        EnemyStateMachine::Tumbling(state)
+    }
+}
+
+impl From<EnemyState<Gone>> for EnemyStateMachine {
+    fn from(state: EnemyState<Gone>) -> Self {
+        EnemyStateMachine::Gone(state)
+    }
+}
+
+impl From<PatrollingEndState> for EnemyStateMachine {
+    fn from(end_state: PatrollingEndState) -> Self {
+        match end_state {
+            PatrollingEndState::Spotted(chasing_state) => chasing_state.into(),
+            PatrollingEndState::Patrolling(patrolling_state) => patrolling_state.into(),
+        }
+    }
+}
+
+impl From<StompedEndState> for EnemyStateMachine {
+    fn from(end_state: StompedEndState) -> Self {
+        match end_state {
+            StompedEndState::Complete(gone_state) => gone_state.into(),
+            StompedEndState::Stomped(stomped_state) => stomped_state.into(),
+        }
+    }
+}
+
+impl From<TumblingEndState> for EnemyStateMachine {
+    fn from(end_state: TumblingEndState) -> Self {
+        match end_state {
+            TumblingEndState::Complete(gone_state) => gone_state.into(),
+            TumblingEndState::Tumbling(tumbling_state) => tumbling_state.into(),
+        }
+    }
+}
+
+pub mod enemy_states {
+    use super::super::HEIGHT;
+    use super::*;
+
+    pub const FRAME_TICKS: u32 = 6;
+    pub const WALKING_FRAMES: u32 = 4;
+    const LEFT_FRAME_NAME: &str = "Left";
+    const RIGHT_FRAME_NAME: &str = "Right";
+    const STOMPED_FRAME_NAME: &str = "Squashed";
+    const TUMBLING_FRAME_NAME: &str = "Tumble";
+    pub const STOMPED_TICKS: u32 = 30;
+    const TUMBLE_VELOCITY: Point = Point::from_pixels(6, -12);
+    const GRAVITY: Fixed = Fixed::from_int(1);
+
+    #[derive(Clone, Hash)]
+    pub struct EnemyState<S> {
+        context: EnemyContext,
+        _state: S,
+    }
+
+    #[derive(Clone, Hash)]
+    pub struct EnemyContext {
+        pub kind: EnemyKind,
+        pub frame: u32,
+        pub home: Point,
+        pub position: Point,
+        pub velocity: Point,
+        pub facing_left: bool,
+    }
+
+    impl EnemyContext {
+        fn tick(mut self) -> Self {
+            self.frame += 1;
+            self
+        }
+
+        fn reset_frame(mut self) -> Self {
+            self.frame = 0;
+            self
+        }
+
+        fn follow(mut self, distance: Fixed) -> Self {
+            let x = enemy_x(self.kind, self.home.x, distance);
+            if x != self.position.x {
+                self.facing_left = x < self.position.x;
+            }
+            self.position.x = x;
+            self
+        }
+
+        fn fly(mut self) -> Self {
+            self.velocity.y += GRAVITY;
+            self.position.x += self.velocity.x;
+            self.position.y += self.velocity.y;
+            self
+        }
+    }
+
+    #[derive(Copy, Clone, Hash)]
+    pub struct Patrolling;
+    #[derive(Copy, Clone, Hash)]
+    pub struct Chasing;
+    #[derive(Copy, Clone, Hash)]
+    pub struct Stomped;
+    #[derive(Copy, Clone, Hash)]
+    pub struct Tumbling;
+    #[derive(Copy, Clone, Hash)]
+    pub struct Gone;
+
+    impl EnemyState<Patrolling> {
+        pub fn new(kind: EnemyKind, home: Point) -> Self {
+            EnemyState {
+                context: EnemyContext {
+                    kind,
+                    frame: 0,
+                    home,
+                    position: home,
+                    velocity: Point::default(),
+                    facing_left: true,
+                },
+                _state: Patrolling,
+            }
+        }
+        pub fn frame_name(&self) -> &str {
+            if self.context.facing_left {
+                LEFT_FRAME_NAME
+            } else {
+                RIGHT_FRAME_NAME
+            }
+        }
+        pub fn update(mut self, distance: Fixed) -> PatrollingEndState {
+            self.context = self.context.tick().follow(distance);
+            if is_spotted(self.context.kind, self.context.home.x, distance) {
+                PatrollingEndState::Spotted(self.chase())
+            } else {
+                PatrollingEndState::Patrolling(self)
+            }
+        }
+        fn chase(self) -> EnemyState<Chasing> {
+            EnemyState {
+                context: self.context,
+                _state: Chasing,
+            }
+        }
+        pub fn stomp(self) -> EnemyState<Stomped> {
+            EnemyState {
+                context: self.context.reset_frame(),
+                _state: Stomped,
+            }
+        }
+        pub fn tumble(self) -> EnemyState<Tumbling> {
+            EnemyState {
+                context: EnemyContext {
+                    velocity: TUMBLE_VELOCITY,
+                    ..self.context
+                },
+                _state: Tumbling,
+            }
+        }
+    }
+    pub enum PatrollingEndState {
+        Spotted(EnemyState<Chasing>),
+        Patrolling(EnemyState<Patrolling>),
+    }
+
+    impl EnemyState<Chasing> {
+        pub fn frame_name(&self) -> &str {
+            LEFT_FRAME_NAME
+        }
+        pub fn update(mut self, distance: Fixed) -> Self {
+            // Charging runs the legs twice as fast as pacing about.
+            self.context = self.context.tick().tick().follow(distance);
+            self
+        }
+        pub fn stomp(self) -> EnemyState<Stomped> {
+            EnemyState {
+                context: self.context.reset_frame(),
+                _state: Stomped,
+            }
+        }
+        pub fn tumble(self) -> EnemyState<Tumbling> {
+            EnemyState {
+                context: EnemyContext {
+                    velocity: TUMBLE_VELOCITY,
+                    ..self.context
+                },
+                _state: Tumbling,
+            }
+        }
+    }
+
+    impl EnemyState<Stomped> {
+        pub fn frame_name(&self) -> &str {
+            STOMPED_FRAME_NAME
+        }
+        pub fn update(mut self) -> StompedEndState {
+            self.context = self.context.tick();
+            if self.context.frame >= STOMPED_TICKS {
+                StompedEndState::Complete(self.vanish())
+            } else {
+                StompedEndState::Stomped(self)
+            }
+        }
+        fn vanish(self) -> EnemyState<Gone> {
+            EnemyState {
+                context: self.context,
+                _state: Gone,
+            }
+        }
+    }
+    pub enum StompedEndState {
+        Complete(EnemyState<Gone>),
+        Stomped(EnemyState<Stomped>),
+    }
+
+    impl EnemyState<Tumbling> {
+        pub fn frame_name(&self) -> &str {
+            TUMBLING_FRAME_NAME
+        }
+        pub fn update(mut self) -> TumblingEndState {
+            self.context = self.context.fly();
+            if self.context.position.y > HEIGHT.into() {
+                TumblingEndState::Complete(self.vanish())
+            } else {
+                TumblingEndState::Tumbling(self)
+            }
+        }
+        fn vanish(self) -> EnemyState<Gone> {
+            EnemyState {
+                context: self.context,
+                _state: Gone,
+            }
+        }
+    }
+    pub enum TumblingEndState {
+        Complete(EnemyState<Gone>),
+        Tumbling(EnemyState<Tumbling>),
+    }
+
+    impl EnemyState<Gone> {
+        pub fn frame_name(&self) -> &str {
+            STOMPED_FRAME_NAME
+        }
+    }
+
+    impl<S> EnemyState<S> {
+        pub fn context(&self) -> &EnemyContext {
+            &self.context
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::HEIGHT;
+
+    const HOME: Point = Point::from_pixels(2000, 470);
+
+    // A standing frame: head and body.
+    fn boy(x: i32, bottom: i32) -> [Rect; 2] {
+        [
+            Rect::from_pixels(x - 6, bottom - 115, 45, 36),
+            Rect::from_pixels(x, bottom - 79, 37, 79),
+        ]
+    }
+
+    // A slide frame, low and long.
+    fn sliding_boy(x: i32, bottom: i32) -> [Rect; 1] {
+        [Rect::from_pixels(x, bottom - 50, 70, 50)]
+    }
+
+    fn contact(boy_boxes: &[Rect], motion: Point, sliding: bool) -> EnemyContact {
+        enemy_contact(boy_boxes, motion, sliding, &EnemyKind::Dog.hitbox(HOME))
+    }
+
+    #[test]
+    fn coming_down_on_the_top_half_stomps() {
+        // The dog's box spans 480 to 524, so its top half ends at 502.
+        assert!(matches!(
+            contact(&boy(2010, 490), Point::from_pixels(5, 20), false),
+            EnemyContact::Stomp
+        ));
+    }
+
+    #[test]
+    fn a_long_fall_onto_the_top_still_stomps() {
+        // His feet end the tick below the dog's middle but started above it.
+        assert!(matches!(
+            contact(&boy(2010, 515), Point::from_pixels(5, 60), false),
+            EnemyContact::Stomp
+        ));
+    }
+
+    #[test]
+    fn sliding_into_the_side_tumbles_it() {
+        assert!(matches!(
+            contact(&sliding_boy(1950, 524), Point::from_pixels(10, 0), true),
+            EnemyContact::Tumble
+        ));
+    }
+
+    #[test]
+    fn any_other_touch_knocks_out() {
+        assert!(matches!(
+            contact(&boy(1980, 524), Point::from_pixels(10, 0), false),
+            EnemyContact::KnockOut
+        ));
+        // Falling, but with his feet already below its middle.
+        assert!(matches!(
+            contact(&boy(2010, 515), Point::from_pixels(5, 5), false),
+            EnemyContact::KnockOut
+        ));
+    }
+
+    #[test]
+    fn passing_by_touches_nothing() {
+        assert!(matches!(
+            contact(&boy(1900, 524), Point::from_pixels(10, 0), false),
+            EnemyContact::None
+        ));
+        assert!(matches!(
+            contact(&boy(2010, 470), Point::from_pixels(5, 20), false),
+            EnemyContact::None
+        ));
+    }
+
+    #[test]
+    fn patrols_around_home_until_the_boy_is_in_sight() {
+        let spotted = HOME.x - DOG.sight;
+        let mut distance = Fixed::ZERO;
+        while distance < spotted {
+            let x = enemy_x(EnemyKind::Dog, HOME.x, distance);
+            assert!((x - HOME.x).abs() <= DOG.patrol);
+            assert!(!is_spotted(EnemyKind::Dog, HOME.x, distance));
+            distance += Fixed::from_int(7);
+        }
+        assert!(is_spotted(EnemyKind::Dog, HOME.x, spotted));
+    }
+
+    #[test]
+    fn chases_at_a_fraction_of_his_speed_from_first_sight() {
+        let spotted = HOME.x - DOG.sight;
+        let start = enemy_x(EnemyKind::Dog, HOME.x, spotted);
+        assert_eq!(
+            enemy_x(EnemyKind::Dog, HOME.x, spotted + Fixed::from_int(100)),
+            start - Fixed::from_int(50)
+        );
+    }
+
+    #[test]
+    fn the_chase_stops_at_its_limit() {
+        let spotted = HOME.x - DOG.sight;
+        let start = enemy_x(EnemyKind::Dog, HOME.x, spotted);
+        let furthest = start - DOG.chase_limit;
+        for run in [500, 1000, 5000] {
+            let x = enemy_x(EnemyKind::Dog, HOME.x, spotted + Fixed::from_int(run));
+            assert_eq!(x, furthest);
+            assert!(x >= HOME.x - EnemyKind::Dog.reach());
+        }
+    }
+
+    #[test]
+    fn a_stomped_enemy_is_gone_once_it_has_lain_squashed() {
+        let mut state = EnemyStateMachine::Patrolling(EnemyState::new(EnemyKind::Dog, HOME))
+            .transition(Event::Stomp);
+        for _ in 1..STOMPED_TICKS {
+            state = state.transition(Event::Update(Fixed::ZERO));
+            assert!(matches!(state, EnemyStateMachine::Stomped(_)));
+            assert!(!state.is_dangerous());
+        }
+        state = state.transition(Event::Update(Fixed::ZERO));
+        assert!(matches!(state, EnemyStateMachine::Gone(_)));
+    }
+
+    #[test]
+    fn a_tumbling_enemy_is_gone_once_it_falls_off_the_screen() {
+        let mut state = EnemyStateMachine::Patrolling(EnemyState::new(EnemyKind::Bird, HOME))
+            .transition(Event::Tumble);
+        let bottom = Fixed::from(HEIGHT);
+        while let EnemyStateMachine::Tumbling(_) = state {
+            assert!(state.context().position.y <= bottom);
+            state = state.transition(Event::Update(Fixed::ZERO));
+        }
+        assert!(matches!(state, EnemyStateMachine::Gone(_)));
+        assert!(state.context().position.y > bottom);
+    }
+}
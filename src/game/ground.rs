@@ -0,0 +1,138 @@
+use super::obstacles::{spawn_platform, PlatformMotion};
+use super::world::GameWorld;
+use super::HEIGHT;
+use crate::engine::{Collider, ColliderKind, Fixed, Point, Rect, SpriteSheet};
+use std::rc::Rc;
+
+const TILE_PIXELS: i32 = 128;
+pub const GROUND_SHOWN_PIXELS: i32 = 40;
+pub const TILE_SIZE: Fixed = Fixed::from_int(TILE_PIXELS);
+// The surface of the ground, where the boy's feet are while he runs on it.
+pub const GROUND_TOP: Fixed = Fixed::from_int(HEIGHT as i32);
+// How far below the surface the game camera looks, so a strip of the ground
+// shows along the bottom of the screen.
+pub const GROUND_SHOWN: Fixed = Fixed::from_int(GROUND_SHOWN_PIXELS);
+// Once the boy's top is past the bottom of the screen he has gone down a pit.
+pub const FALL_LIMIT: Fixed = Fixed::from_int(HEIGHT as i32 + GROUND_SHOWN_PIXELS);
+// Narrower than this a pit could fall between columns and open up nothing.
+pub const PIT_MIN_WIDTH: Fixed = Fixed::from_int(TILE_PIXELS * 2);
+const LEFT_EDGE: &str = "1.png";
+const MIDDLE: &str = "2.png";
+const RIGHT_EDGE: &str = "7.png";
+
+// A stretch of the world with the ground left out under it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pit {
+    pub left: Fixed,
+    pub right: Fixed,
+}
+
+// The ground is a row of tile-wide columns fixed in the world. A column is
+// left out when it lies wholly inside a pit, so which columns are solid
+// depends on nothing but the pits, and the reachability checker builds
+// exactly the ground the game lays.
+fn column(x: Fixed) -> i32 {
+    x.floor().div_euclid(TILE_PIXELS)
+}
+
+fn column_box(column: i32) -> Rect {
+    Rect::new_from_x_y(
+        Fixed::from_int(column * TILE_PIXELS),
+        GROUND_TOP,
+        TILE_SIZE,
+        TILE_SIZE,
+    )
+}
+
+fn is_solid(column: i32, pits: &[Pit]) -> bool {
+    let column = column_box(column);
+    !pits
+        .iter()
+        .any(|pit| pit.left <= column.x() && column.right() <= pit.right)
+}
+
+// Every solid column with any part between `left` and `right`.
+pub fn ground_boxes(left: Fixed, right: Fixed, pits: &[Pit]) -> Vec<Rect> {
+    (column(left)..=column(right))
+        .filter(|column| is_solid(*column, pits))
+        .map(column_box)
+        .collect()
+}
+
+// Lays the ground a column at a time as segments are placed. Pits have to be
+// dug before the ground over them is laid, so ground only ever goes down as
+// far as the segments placed so far.
+pub struct Ground {
+    sheet: Rc<SpriteSheet>,
+    pits: Vec<Pit>,
+    next_column: i32,
+}
+impl Ground {
+    // Starts a column behind the origin, under the boy's starting point.
+    pub fn new(sheet: Rc<SpriteSheet>) -> Self {
+        Ground {
+            sheet,
+            pits: Vec::new(),
+            next_column: -1,
+        }
+    }
+
+    pub fn dig(&mut self, pits: impl IntoIterator<Item = Pit>) {
+        self.pits.extend(pits);
+    }
+
+    // Stops a column short of `right`, so whether the last column laid is at
+    // the edge of a pit is already known when its tile is picked.
+    pub fn lay(&mut self, world: &mut GameWorld, right: Fixed) {
+        let tile_box = Rect::new(Point::default(), TILE_SIZE, TILE_SIZE);
+        while column_box(self.next_column + 1).right() <= right {
+            let column = self.next_column;
+            if is_solid(column, &self.pits) {
+                let tile = if !is_solid(column - 1, &self.pits) {
+                    LEFT_EDGE
+                } else if !is_solid(column + 1, &self.pits) {
+                    RIGHT_EDGE
+                } else {
+                    MIDDLE
+                };
+                let entity = spawn_platform(
+                    world,
+                    &self.sheet,
+                    column_box(column).position,
+                    &[tile.to_string()],
+                    &[tile_box],
+                    PlatformMotion::Still,
+                );
+                world.set_collider(
+                    entity,
+                    Collider {
+                        kind: ColliderKind::Ground,
+                        bounding_boxes: vec![tile_box],
+                    },
+                );
+            }
+            self.next_column += 1;
+        }
+        // The last column laid still needs its pits to pick the next tile.
+        let last = column_box(self.next_column - 1).x();
+        self.pits.retain(|pit| pit.right > last);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_columns_wholly_inside_a_pit_are_left_out() {
+        let pits = [Pit {
+            left: Fixed::from_int(100),
+            right: Fixed::from_int(400),
+        }];
+        let columns: Vec<i32> = ground_boxes(Fixed::ZERO, Fixed::from_int(600), &pits)
+            .iter()
+            .map(|bounding_box| bounding_box.x().floor())
+            .collect();
+        assert_eq!(columns, vec![0, 384, 512]);
+    }
+}
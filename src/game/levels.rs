@@ -0,0 +1,285 @@
+use super::{Walk, OBSTACLE_BUFFER, TEXT_COLOR, TICKS_PER_SECOND};
+use crate::engine::{AssetError, Assets, Audio, Fixed, KeyState, Point, Renderer, SpriteSheet};
+use crate::game::difficulty::DifficultyCurve;
+use crate::game::ground::Ground;
+use crate::game::segments::SegmentLibrary;
+use crate::game::world::GameWorld;
+use anyhow::{anyhow, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::rc::Rc;
+
+const FINISH_DISTANCE: Fixed = Fixed::from_int(300);
+const ENDLESS: &str = "Endless";
+
+#[derive(Deserialize)]
+pub struct LevelList {
+    pub levels: Vec<LevelEntry>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct LevelEntry {
+    pub name: String,
+    pub course: String,
+}
+
+// A hand-crafted course is a fixed run of named segments, each placed after
+// the previous one with an optional extra gap.
+#[derive(Deserialize)]
+pub struct CourseDefinition {
+    pub segments: Vec<CourseSegment>,
+}
+
+#[derive(Deserialize)]
+pub struct CourseSegment {
+    pub segment: String,
+    #[serde(default)]
+    pub gap: Fixed,
+}
+
+// Lays the whole course out up front through the same segment builders the
+// endless mode uses; returns where the finish line goes.
+pub fn build_course(
+    definition: &CourseDefinition,
+    segments: &SegmentLibrary,
+    world: &mut GameWorld,
+    ground: &mut Ground,
+) -> Result<Fixed> {
+    let mut problems = Vec::new();
+    let mut timeline = Fixed::ZERO;
+    for entry in &definition.segments {
+        match segments.layouts().index_of(&entry.segment) {
+            Some(index) => {
+                timeline =
+                    segments.spawn(index, timeline + OBSTACLE_BUFFER + entry.gap, world, ground);
+            }
+            None => problems.push(format!("unknown segment '{}'", entry.segment)),
+        }
+    }
+    if definition.segments.is_empty() {
+        problems.push("no segments".to_string());
+    }
+
+    if problems.is_empty() {
+        Ok(timeline + FINISH_DISTANCE)
+    } else {
+        Err(anyhow!("Invalid course:\n  {}", problems.join("\n  ")))
+    }
+}
+
+pub enum Mode {
+    Endless,
+    Course(Course),
+}
+
+pub struct Course {
+    name: String,
+    finish: Fixed,
+    ticks: u32,
+    started: bool,
+    finished: bool,
+    failed: bool,
+}
+impl Course {
+    pub fn new(name: String, finish: Fixed) -> Self {
+        Course {
+            name,
+            finish,
+            ticks: 0,
+            started: false,
+            finished: false,
+            failed: false,
+        }
+    }
+
+    pub fn finish(&self) -> Fixed {
+        self.finish
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // He was knocked out or went down a pit before the finish line.
+    pub fn is_failed(&self) -> bool {
+        self.failed
+    }
+
+    // The clock starts with the first step and stops once the boy crosses
+    // the finish line, or for good once he is out of the run; updates run at
+    // a fixed 60 per second, so counting them is the completion time.
+    pub fn update(&mut self, distance: Fixed, running: bool, out: bool) {
+        if self.finished || self.failed {
+            return;
+        }
+        if out {
+            self.failed = true;
+            return;
+        }
+        self.started |= running;
+        if self.started {
+            self.ticks += 1;
+        }
+        if distance >= self.finish {
+            self.finished = true;
+        }
+    }
+
+    pub fn time(&self) -> String {
+        format!(
+            "{}.{:02}",
+            self.ticks / TICKS_PER_SECOND,
+            self.ticks % TICKS_PER_SECOND * 100 / TICKS_PER_SECOND
+        )
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Clone)]
+pub struct LevelSelect {
+    assets: Rc<Assets>,
+    audio: Audio,
+    levels: Vec<LevelEntry>,
+    selected: usize,
+    failure: Option<String>,
+}
+impl LevelSelect {
+    // Every course, the segments and the difficulty curve are parsed here,
+    // once the assets are in, so a broken data file is reported by name on
+    // the loading screen rather than on selection.
+    pub fn new(assets: Rc<Assets>, audio: Audio) -> Result<Self, AssetError> {
+        let list: LevelList = assets.data("levels")?;
+        for level in &list.levels {
+            assets.data::<CourseDefinition>(&level.course)?;
+        }
+        assets.data::<DifficultyCurve>("difficulty")?;
+        SegmentLibrary::new(
+            &assets,
+            "segments",
+            Rc::new(SpriteSheet::new(
+                assets.sheet("tiles")?,
+                assets.texture("tiles")?,
+            )),
+        )?;
+        Ok(LevelSelect {
+            assets,
+            audio,
+            levels: list.levels,
+            selected: 0,
+            failure: None,
+        })
+    }
+
+    pub fn assets(&self) -> &Assets {
+        &self.assets
+    }
+
+    pub fn audio(&self) -> Audio {
+        self.audio.clone()
+    }
+
+    // The first entry is the endless run, the rest are the courses in the
+    // order the level list gives them.
+    pub fn update(&mut self, keystate: &KeyState) -> Option<Walk> {
+        let entries = self.levels.len() + 1;
+        if keystate.was_just_pressed("ArrowDown") {
+            self.selected = (self.selected + 1) % entries;
+        }
+        if keystate.was_just_pressed("ArrowUp") {
+            self.selected = (self.selected + entries - 1) % entries;
+        }
+        if !keystate.was_just_pressed("Enter") {
+            return None;
+        }
+
+        let level = self
+            .selected
+            .checked_sub(1)
+            .map(|index| &self.levels[index]);
+        // Logged so the run can be replayed; nothing else in it is random.
+        let seed: u64 = StdRng::from_entropy().gen();
+        log!("Run seed {:016x}", seed);
+        match Walk::new(self, level, seed) {
+            Ok(walk) => Some(walk),
+            Err(err) => {
+                log!("Could not start level {:#?}", err);
+                self.failure = Some(format!("{:#}", err));
+                None
+            }
+        }
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        let mut position = Point::from_pixels(40, 60);
+        renderer.collect(renderer.draw_text("Choose a level", &position, TEXT_COLOR));
+        let names =
+            std::iter::once(ENDLESS).chain(self.levels.iter().map(|level| level.name.as_str()));
+        for (index, name) in names.enumerate() {
+            position.y += Fixed::from_int(30);
+            let marker = if index == self.selected { ">" } else { " " };
+            renderer.collect(renderer.draw_text(
+                &format!("{} {}", marker, name),
+                &position,
+                TEXT_COLOR,
+            ));
+        }
+        position.y += Fixed::from_int(50);
+        renderer.collect(renderer.draw_text(
+            "Up/Down to choose, Enter to start, Escape to come back",
+            &position,
+            TEXT_COLOR,
+        ));
+        if let Some(failure) = &self.failure {
+            for line in failure.lines() {
+                position.y += Fixed::from_int(30);
+                let line: String = line.chars().take(70).collect();
+                renderer.collect(renderer.draw_text(&line, &position, TEXT_COLOR));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{AssetKind, AssetManifest};
+
+    // The game's own assets, with the data asset `id` read from `path`
+    // instead.
+    fn assets_with(id: &str, path: &str) -> Rc<Assets> {
+        let mut manifest: AssetManifest =
+            serde_json::from_str(include_str!("../../static/assets.json")).unwrap();
+        manifest.data.insert(id.to_string(), path.to_string());
+        Rc::new(Assets::headless(manifest, concat!(env!("CARGO_MANIFEST_DIR"), "/static")).unwrap())
+    }
+
+    #[test]
+    fn a_broken_data_file_is_blamed_by_name() {
+        for id in ["levels", "difficulty", "segments", "course_warm_up"] {
+            let failure = LevelSelect::new(assets_with(id, "rhb_hitboxes.json"), Audio::headless())
+                .err()
+                .unwrap_or_else(|| panic!("{} was accepted", id));
+            assert!(matches!(failure.kind, AssetKind::Data));
+            assert_eq!(failure.id, id);
+            assert_eq!(failure.path, "rhb_hitboxes.json");
+        }
+    }
+
+    #[test]
+    fn the_clock_stops_for_good_when_the_boy_is_out() {
+        let mut course = Course::new("test".to_string(), Fixed::from_int(1000));
+        for _ in 0..30 {
+            course.update(Fixed::ZERO, true, false);
+        }
+        course.update(Fixed::ZERO, true, true);
+        course.update(Fixed::from_int(2000), true, false);
+
+        assert!(course.is_failed());
+        assert!(!course.is_finished());
+        assert_eq!(course.time(), "0.50");
+    }
+}
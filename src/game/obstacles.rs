@@ -1,145 +1,627 @@
-use super::rhb::RedHatBoy;
-use crate::engine::{Cell, Image, Point, Rect, Renderer, SpriteSheet};
+use super::rhb::{PowerUp, RedHatBoy};
+use super::world::{Behaviour, GameWorld};
+use crate::engine::{
+    collide, Collider, ColliderKind, Entity, Fixed, Point, Rect, Side, Sprite, SpriteSheet, Texture,
+};
+use serde::{Deserialize, Serialize};
 use std::rc::Rc;
-pub trait Obstacle {
-    fn check_intersection(&self, boy: &mut RedHatBoy);
-    fn draw(&self, renderer: &Renderer);
-    fn move_horizontally(&mut self, x: i16);
-    fn draw_bounding_box(&self, renderer: &Renderer);
-    fn right(&self) -> i16;
+
+#[derive(Clone, Copy, Hash)]
+pub struct Pickup {
+    pub score: u32,
+    pub power_up: Option<PowerUp>,
 }
 
-pub struct Platform {
-    sheet: Rc<SpriteSheet>,
-    position: Point,
-    bounding_boxes: Vec<Rect>,
-    sprites: Vec<Cell>,
-}
-impl Platform {
-    pub fn new(
-        sheet: Rc<SpriteSheet>,
-        position: Point,
-        sprite_names: &[&str],
-        bounding_boxes: &[Rect],
-    ) -> Self {
-        let sprites = sprite_names
-            .iter()
-            .filter_map(|sprite_name| sheet.cell(sprite_name).cloned())
-            .collect();
-        let bounding_boxes = bounding_boxes
-            .iter()
-            .map(|bounding_box| {
-                Rect::new_from_x_y(
-                    bounding_box.x() + position.x,
-                    bounding_box.y() + position.y,
-                    bounding_box.width,
-                    bounding_box.height,
-                )
+const MAGNET_RANGE: Fixed = Fixed::from_int(250);
+const MAGNET_SPEED: Fixed = Fixed::from_int(9);
+const CRUMBLE_TICKS: u32 = 20;
+const CRUMBLE_SHAKE: Fixed = Fixed::from_int(2);
+const CRUMBLE_GRAVITY: Fixed = Fixed::from_int(1);
+const ROLL_TRIGGER: Fixed = Fixed::from_int(500);
+pub const ROLL_LIMIT: Fixed = Fixed::from_int(240);
+const ROCK_RADIUS: f64 = 40.0;
+const ROCK_ANGLE: f64 = 0.15;
+
+#[derive(Clone, Copy, Default, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum PlatformMotion {
+    #[default]
+    Still,
+    // Rises by `height` and sinks back once every `period` pixels run.
+    Bob {
+        height: Fixed,
+        period: Fixed,
+    },
+    // Gives way shortly after the boy first lands on it.
+    Crumble,
+}
+impl PlatformMotion {
+    pub fn is_still(&self) -> bool {
+        *self == PlatformMotion::Still
+    }
+}
+
+// Moving obstacles follow how far the boy has run rather than the clock. He
+// covers the same ground in the same ticks on every run, so the reachability
+// checker can replay them exactly from his position alone.
+pub fn bob_offset(distance: Fixed, height: Fixed, period: Fixed) -> Fixed {
+    let along = distance.rem_euclid(period) / period;
+    let up = Fixed::from_int(1) - (along * 2 - Fixed::from_int(1)).abs();
+    -(height * up)
+}
+
+// A rolling stone sets off once the boy is within `ROLL_TRIGGER` of it, at
+// half his speed, and comes to rest after `ROLL_LIMIT`.
+pub fn rolled_distance(distance: Fixed, start_x: Fixed) -> Fixed {
+    ((distance - (start_x - ROLL_TRIGGER)).max(Fixed::ZERO) / 2).min(ROLL_LIMIT)
+}
+
+pub enum Contact {
+    None,
+    Land(Fixed),
+    // How far he has to drop to come back out from under it.
+    HitCeiling(Fixed),
+    KnockOut,
+}
+
+// The contact rules are plain functions of the boy's box and motion so the
+// reachability checker can replay them without a live `RedHatBoy`. `motion`
+// is how far he has moved since the tick began; his box is swept along it,
+// so he can't pass through an edge between one tick and the next. The first
+// box he meets decides what happens by the side he meets it on: he lands on
+// its top, bumps his head on its bottom and is knocked out by its sides.
+pub fn platform_contact(boy_boxes: &[Rect], motion: Point, bounding_boxes: &[Rect]) -> Contact {
+    let first = boy_boxes
+        .iter()
+        .flat_map(|hitbox| {
+            bounding_boxes.iter().filter_map(move |bounding_box| {
+                collide(&start_of_tick(hitbox, motion), motion, bounding_box)
+                    .map(|collision| (collision, bounding_box))
             })
-            .collect();
-        Platform {
-            sheet,
-            position,
-            sprites,
-            bounding_boxes,
-        }
+        })
+        .min_by_key(|(collision, _)| collision.time);
+    match first {
+        Some((collision, bounding_box)) => match collision.side {
+            Side::Top => Contact::Land(bounding_box.y()),
+            Side::Bottom => Contact::HitCeiling(collision.depth),
+            Side::Left | Side::Right => Contact::KnockOut,
+        },
+        None => Contact::None,
     }
-    fn bounding_boxes(&self) -> &Vec<Rect> {
-        &self.bounding_boxes
+}
+
+pub fn barrier_contact(boy_boxes: &[Rect], motion: Point, barrier: &Rect) -> Contact {
+    if boy_boxes
+        .iter()
+        .any(|hitbox| collide(&start_of_tick(hitbox, motion), motion, barrier).is_some())
+    {
+        Contact::KnockOut
+    } else {
+        Contact::None
     }
 }
-impl Obstacle for Platform {
-    fn check_intersection(&self, boy: &mut RedHatBoy) {
-        if let Some(box_to_land_on) = self
-            .bounding_boxes()
-            .iter()
-            .find(|&bounding_box| boy.bounding_box().intersects(bounding_box))
-        {
-            if boy.velocity_y() > 0 && boy.pos_y() < self.position.y {
-                boy.land_on(box_to_land_on.y());
-            } else {
-                if boy.velocity_y() < 0 && boy.pos_y() > self.position.y {
-                    boy.hit_ceiling();
+
+// Something hanging low is run into like a barrier, so it only misses him
+// if his slide frames' hitboxes pass under it. He is held in his slide while
+// he is under it or will be by the next tick, so the slide can't run out and
+// stand him up into it.
+pub fn is_beneath(boy_boxes: &[Rect], motion: Point, overhead: &Rect) -> bool {
+    match Rect::enclosing(boy_boxes) {
+        Some(bounds) => {
+            bounds.x() < overhead.right()
+                && bounds.right() + motion.x.max(Fixed::ZERO) > overhead.x()
+        }
+        None => false,
+    }
+}
+
+pub fn start_of_tick(hitbox: &Rect, motion: Point) -> Rect {
+    Rect::new_from_x_y(
+        hitbox.x() - motion.x,
+        hitbox.y() - motion.y,
+        hitbox.width,
+        hitbox.height,
+    )
+}
+
+#[derive(Clone, Copy, Hash)]
+enum Crumbling {
+    Intact,
+    Shaking(u32),
+    Falling,
+}
+
+// Drives a platform that doesn't stay put; still platforms have no behaviour.
+#[derive(Hash)]
+pub struct MovingPlatform {
+    rest_y: Fixed,
+    motion: PlatformMotion,
+    crumbling: Crumbling,
+}
+impl MovingPlatform {
+    pub fn update(&mut self, entity: Entity, world: &mut GameWorld, distance: Fixed) {
+        match self.motion {
+            PlatformMotion::Still => {}
+            PlatformMotion::Bob { height, period } => {
+                let position = world.position(entity);
+                world.set_position(
+                    entity,
+                    Point {
+                        x: position.x,
+                        y: self.rest_y + bob_offset(distance, height, period),
+                    },
+                );
+            }
+            PlatformMotion::Crumble => {
+                self.crumbling = match self.crumbling {
+                    Crumbling::Intact => Crumbling::Intact,
+                    // A crumbled platform is just falling rubble; nothing
+                    // stands on it.
+                    Crumbling::Shaking(0) => {
+                        world.remove_collider(entity);
+                        world.velocities.insert(entity, Point::default());
+                        Crumbling::Falling
+                    }
+                    Crumbling::Shaking(ticks) => Crumbling::Shaking(ticks - 1),
+                    Crumbling::Falling => {
+                        if let Some(velocity) = world.velocities.get_mut(&entity) {
+                            velocity.y += CRUMBLE_GRAVITY;
+                        }
+                        Crumbling::Falling
+                    }
+                };
+                if let Some(Sprite::Cells { offset, .. }) = world.sprites.get_mut(&entity) {
+                    offset.x = match self.crumbling {
+                        Crumbling::Shaking(ticks) if ticks / 2 % 2 == 0 => CRUMBLE_SHAKE,
+                        Crumbling::Shaking(_) => -CRUMBLE_SHAKE,
+                        _ => Fixed::ZERO,
+                    };
                 }
-                boy.knock_out();
             }
         }
     }
-    fn draw(&self, renderer: &Renderer) {
-        let mut x = 0;
-        self.sprites.iter().for_each(|sprite| {
-            self.sheet.draw(
-                renderer,
-                &Rect::new_from_x_y(
-                    sprite.frame.x,
-                    sprite.frame.y,
-                    sprite.frame.w,
-                    sprite.frame.h,
-                ),
-                &Rect::new_from_x_y(
-                    self.position.x + x,
-                    self.position.y,
-                    sprite.frame.w,
-                    sprite.frame.h,
-                ),
-            );
-            x += sprite.frame.w;
-        });
+
+    pub fn landed_on(&mut self) {
+        if let (PlatformMotion::Crumble, Crumbling::Intact) = (self.motion, self.crumbling) {
+            self.crumbling = Crumbling::Shaking(CRUMBLE_TICKS);
+        }
     }
-    fn move_horizontally(&mut self, x: i16) {
-        self.position.x += x;
-        self.bounding_boxes.iter_mut().for_each(|bounding_box| {
-            bounding_box.set_x(bounding_box.position.x + x);
-        });
+}
+
+// A barrier that rolls towards the boy as he approaches, rocking on its flat
+// base as it goes.
+#[derive(Hash)]
+pub struct RollingBarrier {
+    start_x: Fixed,
+}
+impl RollingBarrier {
+    pub fn update(&mut self, entity: Entity, world: &mut GameWorld, distance: Fixed) {
+        let rolled = rolled_distance(distance, self.start_x);
+        let position = world.position(entity);
+        world.set_position(
+            entity,
+            Point {
+                x: self.start_x - rolled,
+                y: position.y,
+            },
+        );
+        if let Some(Sprite::Image { angle, .. }) = world.sprites.get_mut(&entity) {
+            *angle = -(rolled.to_pixels() as f64 / ROCK_RADIUS).sin() * ROCK_ANGLE;
+        }
     }
-    fn draw_bounding_box(&self, renderer: &Renderer) {
-        for bounding_box in self.bounding_boxes() {
-            renderer.draw_rect(&bounding_box);
+}
+
+impl Pickup {
+    // While the boy has a magnet, pickups in range drift towards his middle.
+    pub fn update(&self, entity: Entity, world: &mut GameWorld, boy: &RedHatBoy) {
+        world.velocities.remove(&entity);
+        if let (true, Some(bounding_box), Some(target)) = (
+            boy.power_up_remaining(PowerUp::Magnet) > 0,
+            world.bounding_boxes(entity).first(),
+            Rect::enclosing(&boy.bounding_boxes()),
+        ) {
+            let dx = target.x() + target.width / 2 - (bounding_box.x() + bounding_box.width / 2);
+            let dy = target.y() + target.height / 2 - (bounding_box.y() + bounding_box.height / 2);
+            if dx.abs() < MAGNET_RANGE && dy.abs() < MAGNET_RANGE {
+                world.velocities.insert(
+                    entity,
+                    Point {
+                        x: dx.clamp(-MAGNET_SPEED, MAGNET_SPEED),
+                        y: dy.clamp(-MAGNET_SPEED, MAGNET_SPEED),
+                    },
+                );
+            }
         }
     }
-    fn right(&self) -> i16 {
-        self.bounding_boxes()
-            .last()
-            .unwrap_or(&Rect::default())
-            .right()
+}
+
+pub fn spawn_platform(
+    world: &mut GameWorld,
+    sheet: &Rc<SpriteSheet>,
+    position: Point,
+    sprite_names: &[String],
+    bounding_boxes: &[Rect],
+    motion: PlatformMotion,
+) -> Entity {
+    let entity = world.spawn(position);
+    world.sprites.insert(
+        entity,
+        Sprite::Cells {
+            sheet: sheet.clone(),
+            names: sprite_names.to_vec(),
+            offset: Point::default(),
+        },
+    );
+    world.set_collider(
+        entity,
+        Collider {
+            kind: ColliderKind::Solid,
+            bounding_boxes: bounding_boxes.to_vec(),
+        },
+    );
+    if !motion.is_still() {
+        world.behaviours.insert(
+            entity,
+            Behaviour::Platform(MovingPlatform {
+                rest_y: position.y,
+                motion,
+                crumbling: Crumbling::Intact,
+            }),
+        );
     }
+    entity
 }
 
-pub struct Barrier {
-    image: Image,
+// A platform's sprites and boxes hung up as a low ceiling.
+pub fn spawn_overhead(
+    world: &mut GameWorld,
+    sheet: &Rc<SpriteSheet>,
+    position: Point,
+    sprite_names: &[String],
+    bounding_boxes: &[Rect],
+) -> Entity {
+    let entity = spawn_platform(
+        world,
+        sheet,
+        position,
+        sprite_names,
+        bounding_boxes,
+        PlatformMotion::Still,
+    );
+    world.set_collider(
+        entity,
+        Collider {
+            kind: ColliderKind::Overhead,
+            bounding_boxes: bounding_boxes.to_vec(),
+        },
+    );
+    entity
 }
-impl Barrier {
-    pub fn new(image: Image) -> Self {
-        Barrier { image }
+
+pub fn spawn_barrier(
+    world: &mut GameWorld,
+    texture: &Texture,
+    position: Point,
+    rolling: bool,
+) -> Entity {
+    let entity = world.spawn(position);
+    world.set_collider(
+        entity,
+        Collider {
+            kind: ColliderKind::Hazard,
+            bounding_boxes: vec![Rect::new(
+                Point::default(),
+                texture.width().into(),
+                texture.height().into(),
+            )],
+        },
+    );
+    world.sprites.insert(
+        entity,
+        Sprite::Image {
+            texture: texture.clone(),
+            angle: 0.0,
+        },
+    );
+    if rolling {
+        world.behaviours.insert(
+            entity,
+            Behaviour::Rolling(RollingBarrier {
+                start_x: position.x,
+            }),
+        );
     }
+    entity
 }
-impl Obstacle for Barrier {
-    fn check_intersection(&self, boy: &mut RedHatBoy) {
-        if boy.bounding_box().intersects(self.image.bounding_box()) {
-            boy.knock_out();
+
+pub fn spawn_collectible(
+    world: &mut GameWorld,
+    sheet: &Rc<SpriteSheet>,
+    position: Point,
+    sprite_name: &str,
+    pickup: Pickup,
+) -> Entity {
+    let entity = world.spawn(position);
+    let (width, height) = sheet
+        .cell(sprite_name)
+        .map(|sprite| (sprite.frame.w.into(), sprite.frame.h.into()))
+        .unwrap_or_default();
+    world.set_collider(
+        entity,
+        Collider {
+            kind: ColliderKind::Sensor,
+            bounding_boxes: vec![Rect::new(Point::default(), width, height)],
+        },
+    );
+    world.sprites.insert(
+        entity,
+        Sprite::Frame {
+            sheet: sheet.clone(),
+            name: sprite_name.to_string(),
+        },
+    );
+    world.behaviours.insert(entity, Behaviour::Pickup(pickup));
+    entity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Sheet;
+    use crate::game::ground::GROUND_TOP;
+    use crate::game::rhb::red_hat_boy_states::PLAYER_HEIGHT;
+    use crate::game::rhb::Hitboxes;
+    use std::collections::HashMap;
+
+    // The "floating" platform from segments.json, placed at (400, 420).
+    const FLOATING: [Rect; 3] = [
+        Rect::from_pixels(400, 420, 60, 54),
+        Rect::from_pixels(460, 420, 264, 93),
+        Rect::from_pixels(724, 420, 60, 54),
+    ];
+
+    // A standing frame: head and body.
+    fn boy(x: i32, bottom: i32) -> [Rect; 2] {
+        [
+            Rect::from_pixels(x - 6, bottom - 115, 45, 36),
+            Rect::from_pixels(x, bottom - 79, 37, 79),
+        ]
+    }
+
+    #[test]
+    fn falling_onto_the_top_lands() {
+        assert!(matches!(
+            platform_contact(&boy(500, 430), Point::from_pixels(9, 20), &FLOATING),
+            Contact::Land(top) if top == Fixed::from_int(420)
+        ));
+    }
+
+    #[test]
+    fn running_from_one_box_onto_the_next_keeps_landing() {
+        // Standing on the left box, so gravity takes him through its top.
+        assert!(matches!(
+            platform_contact(&boy(430, 440), Point::from_pixels(10, 20), &FLOATING),
+            Contact::Land(top) if top == Fixed::from_int(420)
+        ));
+    }
+
+    #[test]
+    fn jumping_into_the_bottom_bumps_his_head() {
+        assert!(matches!(
+            platform_contact(&boy(500, 610), Point::from_pixels(9, -20), &FLOATING),
+            Contact::HitCeiling(depth) if depth == Fixed::from_int(18)
+        ));
+    }
+
+    #[test]
+    fn falling_into_the_side_of_the_tall_middle_box_knocks_out() {
+        // Below the left box, so only the middle one is in his way.
+        assert!(matches!(
+            platform_contact(&boy(430, 600), Point::from_pixels(10, 5), &FLOATING),
+            Contact::KnockOut
+        ));
+    }
+
+    #[test]
+    fn running_into_the_far_side_knocks_out() {
+        assert!(matches!(
+            platform_contact(&boy(785, 530), Point::from_pixels(-10, 1), &FLOATING),
+            Contact::KnockOut
+        ));
+    }
+
+    #[test]
+    fn running_underneath_touches_nothing() {
+        assert!(matches!(
+            platform_contact(&boy(500, 640), Point::from_pixels(10, 0), &FLOATING),
+            Contact::None
+        ));
+    }
+
+    fn sheet() -> Rc<SpriteSheet> {
+        Rc::new(SpriteSheet::new(
+            Sheet {
+                frames: HashMap::new(),
+            },
+            Texture::headless(0, 0),
+        ))
+    }
+
+    fn moving_platform(motion: PlatformMotion) -> (GameWorld, Entity) {
+        let mut world = GameWorld::new();
+        let entity = spawn_platform(
+            &mut world,
+            &sheet(),
+            Point::from_pixels(400, 300),
+            &["13.png".to_string()],
+            &[Rect::from_pixels(0, 0, 128, 93)],
+            motion,
+        );
+        (world, entity)
+    }
+
+    // Runs an obstacle's behaviour for one tick with the boy at `distance`,
+    // then moves the world on as `Walk` does.
+    fn tick(world: &mut GameWorld, entity: Entity, distance: Fixed) {
+        let mut behaviour = world.behaviours.remove(&entity).unwrap();
+        match &mut behaviour {
+            Behaviour::Platform(platform) => platform.update(entity, world, distance),
+            Behaviour::Rolling(rolling) => rolling.update(entity, world, distance),
+            _ => unreachable!(),
         }
+        world.behaviours.insert(entity, behaviour);
+        world.movement();
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        self.image.draw(renderer);
+    fn land_on(world: &mut GameWorld, entity: Entity) {
+        if let Some(Behaviour::Platform(platform)) = world.behaviours.get_mut(&entity) {
+            platform.landed_on();
+        }
     }
 
-    fn move_horizontally(&mut self, x: i16) {
-        self.image.move_horizontally(x);
+    fn pixels(world: &GameWorld, entity: Entity) -> (i32, i32) {
+        let position = world.position(entity);
+        (position.x.to_pixels(), position.y.to_pixels())
     }
 
-    fn draw_bounding_box(&self, renderer: &Renderer) {
-        self.image.draw_bounding_box(renderer);
+    fn shake(world: &GameWorld, entity: Entity) -> Fixed {
+        match world.sprites.get(&entity) {
+            Some(Sprite::Cells { offset, .. }) => offset.x,
+            _ => unreachable!(),
+        }
     }
 
-    fn right(&self) -> i16 {
-        self.image.right()
+    #[test]
+    fn a_platform_keeps_the_names_of_cells_its_sheet_is_missing() {
+        // The sheet has no cells at all, so the platform has to be drawn as
+        // a placeholder rather than as nothing.
+        let (world, entity) = moving_platform(PlatformMotion::Still);
+        assert!(matches!(
+            world.sprites.get(&entity),
+            Some(Sprite::Cells { names, .. }) if names == &["13.png".to_string()]
+        ));
+        assert!(world.collider(entity).is_some());
+    }
+
+    #[test]
+    fn a_bobbing_platform_rises_and_sinks_once_every_period() {
+        let (mut world, entity) = moving_platform(PlatformMotion::Bob {
+            height: Fixed::from_int(40),
+            period: Fixed::from_int(400),
+        });
+        for (distance, y) in [
+            (0, 300),
+            (100, 280),
+            (200, 260),
+            (300, 280),
+            (400, 300),
+            (600, 260),
+            (1100, 280),
+        ] {
+            tick(&mut world, entity, Fixed::from_int(distance));
+            assert_eq!(pixels(&world, entity), (400, y), "at {}", distance);
+        }
+    }
+
+    #[test]
+    fn a_crumbling_platform_holds_until_landed_on() {
+        let (mut world, entity) = moving_platform(PlatformMotion::Crumble);
+        for distance in 0..100 {
+            tick(&mut world, entity, Fixed::from_int(distance));
+        }
+        assert_eq!(pixels(&world, entity), (400, 300));
+        assert_eq!(shake(&world, entity), Fixed::ZERO);
+        assert!(world.collider(entity).is_some());
+    }
+
+    #[test]
+    fn a_crumbling_platform_shakes_and_then_falls_once_landed_on() {
+        let (mut world, entity) = moving_platform(PlatformMotion::Crumble);
+        land_on(&mut world, entity);
+        let mut shakes = Vec::new();
+        for _ in 0..CRUMBLE_TICKS {
+            tick(&mut world, entity, Fixed::ZERO);
+            assert_eq!(shake(&world, entity).abs(), CRUMBLE_SHAKE);
+            assert_eq!(pixels(&world, entity), (400, 300));
+            assert!(world.collider(entity).is_some());
+            shakes.push(shake(&world, entity));
+        }
+        assert!(shakes.contains(&CRUMBLE_SHAKE) && shakes.contains(&-CRUMBLE_SHAKE));
+
+        // Landing on it again doesn't put off the fall.
+        land_on(&mut world, entity);
+        tick(&mut world, entity, Fixed::ZERO);
+        assert!(world.collider(entity).is_none());
+        assert_eq!(shake(&world, entity), Fixed::ZERO);
+        let mut y = world.position(entity).y;
+        for _ in 0..10 {
+            tick(&mut world, entity, Fixed::ZERO);
+            assert!(world.position(entity).y > y);
+            y = world.position(entity).y;
+        }
+    }
+
+    #[test]
+    fn a_rolling_barrier_sets_off_as_the_boy_nears_and_stops_at_its_limit() {
+        let mut world = GameWorld::new();
+        let entity = spawn_barrier(
+            &mut world,
+            &Texture::headless(90, 54),
+            Point::from_pixels(2000, 546),
+            true,
+        );
+        // It sets off once he is 500 away and rolls at half his speed.
+        for (distance, x) in [
+            (0, 2000),
+            (1500, 2000),
+            (1700, 1900),
+            (1900, 1800),
+            (1980, 1760),
+            (3000, 1760),
+        ] {
+            tick(&mut world, entity, Fixed::from_int(distance));
+            assert_eq!(pixels(&world, entity), (x, 546), "at {}", distance);
+        }
+    }
+
+    // The boy's hitboxes for `frame` from rhb_hitboxes.json, with him
+    // standing on the ground at `x`.
+    fn frame_boxes(frame: &str, x: i32) -> Vec<Rect> {
+        let hitboxes: Hitboxes =
+            serde_json::from_str(include_str!("../../static/rhb_hitboxes.json")).unwrap();
+        let position = Point {
+            x: Fixed::from_int(x),
+            y: GROUND_TOP - PLAYER_HEIGHT,
+        };
+        hitboxes[frame]
+            .iter()
+            .map(|hitbox| {
+                Rect::new_from_x_y(
+                    position.x + hitbox.x(),
+                    position.y + hitbox.y(),
+                    hitbox.width,
+                    hitbox.height,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn only_the_slide_frames_pass_under_an_overhead() {
+        let overhead = Rect::from_pixels(400, 394, 384, 118);
+        let motion = Point::from_pixels(10, 0);
+        let running = frame_boxes("Run (1).png", 350);
+        assert!(matches!(
+            barrier_contact(&running, motion, &overhead),
+            Contact::KnockOut
+        ));
+        for frame in 1..=5 {
+            let sliding = frame_boxes(&format!("Slide ({}).png", frame), 350);
+            assert!(matches!(
+                barrier_contact(&sliding, motion, &overhead),
+                Contact::None
+            ));
+            assert!(is_beneath(&sliding, motion, &overhead));
+        }
     }
-}
-pub fn rightmost(obstacle_list: &Vec<Box<dyn Obstacle>>) -> i16 {
-    obstacle_list
-        .iter()
-        .map(|obstacle| obstacle.right())
-        .max_by(|x, y| x.cmp(&y))
-        .unwrap_or(0)
 }
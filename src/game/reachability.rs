@@ -0,0 +1,560 @@
+use crate::engine::{Fixed, Point, Rect};
+use crate::game::difficulty::DifficultyCurve;
+use crate::game::enemies::{enemy_x, EnemyKind};
+use crate::game::ground::{ground_boxes, Pit, FALL_LIMIT, GROUND_TOP};
+use crate::game::obstacles::{
+    barrier_contact, bob_offset, is_beneath, platform_contact, rolled_distance, Contact, ROLL_LIMIT,
+};
+use crate::game::rhb::red_hat_boy_states::{
+    physics_step, JUMP_SPEED, PLAYER_HEIGHT, SLIDING_FRAMES,
+};
+use crate::game::segments::SegmentLayouts;
+use crate::game::OBSTACLE_BUFFER;
+use rand::Rng;
+use std::collections::HashSet;
+
+// The box around all of the boy's running and jumping hitboxes from
+// rhb_hitboxes.json, relative to his position. Checking against the largest
+// box keeps the validation on the safe side of any single animation frame.
+const HITBOX: Rect = Rect::from_pixels(69, 9, 60, 112);
+// The same around his sliding ones.
+const SLIDE_HITBOX: Rect = Rect::from_pixels(53, 57, 67, 64);
+const RUN_UP: Fixed = Fixed::from_int(200);
+const SEAM_STEP: Fixed = Fixed::from_int(40);
+const SEAM_SHIFTS: i32 = 6;
+const ATTEMPTS: usize = 8;
+
+// Where obstacles start out. Moving ones are placed wherever the boy's
+// position puts them on each tick, as the live obstacles are.
+pub enum Collider {
+    Platform {
+        bounding_boxes: Vec<Rect>,
+        // Height and period of a bobbing platform.
+        bob: Option<(Fixed, Fixed)>,
+    },
+    Barrier(Rect),
+    RollingBarrier(Rect),
+    // Run into like a barrier; only a slide gets under it.
+    Overhead(Rect),
+    // Stomping is never counted on: an enemy has to be avoided like a barrier.
+    Enemy {
+        kind: EnemyKind,
+        home: Point,
+    },
+    // Blocks nothing itself; the ground it leaves out is what matters.
+    Pit(Pit),
+}
+impl Collider {
+    fn left(&self) -> Fixed {
+        match self {
+            Collider::Platform { bounding_boxes, .. } => bounding_boxes
+                .iter()
+                .map(|bounding_box| bounding_box.x())
+                .min()
+                .unwrap_or(Fixed::ZERO),
+            Collider::Barrier(bounding_box) | Collider::Overhead(bounding_box) => bounding_box.x(),
+            Collider::RollingBarrier(bounding_box) => bounding_box.x() - ROLL_LIMIT,
+            Collider::Enemy { kind, home } => kind.hitbox(*home).x() - kind.reach(),
+            Collider::Pit(pit) => pit.left,
+        }
+    }
+    fn right(&self) -> Fixed {
+        match self {
+            Collider::Platform { bounding_boxes, .. } => bounding_boxes
+                .iter()
+                .map(|bounding_box| bounding_box.right())
+                .max()
+                .unwrap_or(Fixed::ZERO),
+            Collider::Barrier(bounding_box)
+            | Collider::RollingBarrier(bounding_box)
+            | Collider::Overhead(bounding_box) => bounding_box.right(),
+            Collider::Enemy { kind, home } => kind.hitbox(*home).right() + kind.reach(),
+            Collider::Pit(pit) => pit.right,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Runner {
+    position: (Fixed, Fixed),
+    velocity_y: Fixed,
+    jumping: bool,
+    // How far through his slide he is, counted as the game counts frames.
+    sliding: Option<u8>,
+}
+impl Runner {
+    // Running on the ground a little before the given obstacles.
+    fn run_up(colliders: &[Collider]) -> Self {
+        let left = colliders
+            .iter()
+            .map(|collider| collider.left())
+            .min()
+            .unwrap_or(Fixed::ZERO);
+        Runner {
+            position: (left - RUN_UP - HITBOX.right(), GROUND_TOP - PLAYER_HEIGHT),
+            velocity_y: Fixed::ZERO,
+            jumping: false,
+            sliding: None,
+        }
+    }
+    fn hitbox(&self) -> Rect {
+        let frame = if self.sliding.is_some() {
+            SLIDE_HITBOX
+        } else {
+            HITBOX
+        };
+        Rect::new_from_x_y(
+            self.position.0 + frame.x(),
+            self.position.1 + frame.y(),
+            frame.width,
+            frame.height,
+        )
+    }
+    fn left(&self) -> Fixed {
+        self.hitbox().x()
+    }
+}
+
+// What the boy can be told to do on a tick. He can only jump or start a
+// slide while running, as in the game.
+#[derive(Clone, Copy)]
+enum Move {
+    Run,
+    Jump,
+    Slide,
+}
+
+// A segment placed in the world, together with any pit dug into the seam in
+// front of it and every way the boy can be moving at the moment he clears
+// the segment before it. The next segment is validated from those states, so
+// a seam that only works when arriving from a platform is judged by the
+// platforms really in front of it.
+pub struct Placement {
+    pub index: usize,
+    pub offset: Fixed,
+    pub pit: Option<Pit>,
+    pub colliders: Vec<Collider>,
+    arrivals: Vec<Runner>,
+}
+impl Placement {
+    pub fn first(layouts: &SegmentLayouts, index: usize, offset: Fixed) -> Self {
+        let colliders = layouts.colliders(index, offset);
+        Placement {
+            index,
+            offset,
+            pit: None,
+            arrivals: vec![Runner::run_up(&colliders)],
+            colliders,
+        }
+    }
+}
+
+// Picks the next segment the way the difficulty curve wants, but only hands
+// it out once a run through the seam with the previous segment and on to the
+// end of the new one is possible. A failing segment is first pushed further
+// away to widen the seam, then rerolled along with the seam's pit.
+pub fn place_next_segment(
+    layouts: &SegmentLayouts,
+    difficulty: &DifficultyCurve,
+    distance: Fixed,
+    previous: &Placement,
+    timeline: Fixed,
+    rng: &mut impl Rng,
+) -> Option<Placement> {
+    for _ in 0..ATTEMPTS {
+        let index = difficulty.choose_segment(distance, layouts.ratings(), rng);
+        let pit = difficulty.seam_pit(rng).map(|width| Pit {
+            left: timeline + OBSTACLE_BUFFER,
+            right: timeline + OBSTACLE_BUFFER + width,
+        });
+        let start = pit.map_or(timeline, |pit| pit.right);
+        for shift in 0..=SEAM_SHIFTS {
+            let offset = start + OBSTACLE_BUFFER + SEAM_STEP * shift;
+            let mut colliders = layouts.colliders(index, offset);
+            colliders.extend(pit.map(Collider::Pit));
+            if let Some(arrivals) = explore(
+                difficulty,
+                &previous.arrivals,
+                &previous.colliders,
+                &colliders,
+            ) {
+                return Some(Placement {
+                    index,
+                    offset,
+                    pit,
+                    colliders,
+                    arrivals,
+                });
+            }
+        }
+    }
+    None
+}
+
+// Searches every jump and slide timing, tick by tick, from each arrival state through
+// `previous` and on past `next`, stepping exactly as `Walk::update` moves the
+// boy: input, physics, then contacts in obstacle order. Returns the states in
+// which `previous` is cleared when `next` can be cleared at all.
+fn explore(
+    difficulty: &DifficultyCurve,
+    arrivals: &[Runner],
+    previous: &[Collider],
+    next: &[Collider],
+) -> Option<Vec<Runner>> {
+    let colliders: Vec<&Collider> = previous.iter().chain(next.iter()).collect();
+    let pits: Vec<Pit> = colliders
+        .iter()
+        .filter_map(|collider| match collider {
+            Collider::Pit(pit) => Some(*pit),
+            _ => None,
+        })
+        .collect();
+    let cleared = previous
+        .iter()
+        .map(|collider| collider.right())
+        .max()
+        .unwrap_or(Fixed::ZERO);
+    let finish = colliders
+        .iter()
+        .map(|collider| collider.right())
+        .max()
+        .unwrap_or(Fixed::ZERO);
+
+    let mut crossings: Vec<Runner> = arrivals
+        .iter()
+        .filter(|runner| runner.left() > cleared)
+        .copied()
+        .collect();
+    let mut finished = false;
+    let mut seen: HashSet<Runner> = arrivals.iter().copied().collect();
+    let mut pending = arrivals.to_vec();
+    while let Some(runner) = pending.pop() {
+        if runner.left() > finish {
+            finished = true;
+            continue;
+        }
+        let choices: &[Move] = if runner.jumping || runner.sliding.is_some() {
+            &[Move::Run]
+        } else {
+            &[Move::Run, Move::Jump, Move::Slide]
+        };
+        for &input in choices {
+            if let Some(next) = step(difficulty, &colliders, &pits, runner, input) {
+                if next.position.0 > runner.position.0 && seen.insert(next) {
+                    if runner.left() <= cleared && next.left() > cleared {
+                        crossings.push(next);
+                    }
+                    pending.push(next);
+                }
+            }
+        }
+    }
+    if finished {
+        Some(crossings)
+    } else {
+        None
+    }
+}
+
+fn step(
+    difficulty: &DifficultyCurve,
+    colliders: &[&Collider],
+    pits: &[Pit],
+    mut runner: Runner,
+    input: Move,
+) -> Option<Runner> {
+    let from = runner.position;
+    match input {
+        Move::Run => {}
+        Move::Jump => {
+            runner.velocity_y = JUMP_SPEED;
+            runner.jumping = true;
+        }
+        Move::Slide => runner.sliding = Some(0),
+    }
+    // He stands up once the slide's frames have run out.
+    runner.sliding = runner
+        .sliding
+        .map(|frame| frame + 1)
+        .filter(|frame| *frame < SLIDING_FRAMES);
+
+    let (position, velocity) = physics_step(
+        Point {
+            x: runner.position.0,
+            y: runner.position.1,
+        },
+        Point {
+            x: difficulty.speed(runner.position.0),
+            y: runner.velocity_y,
+        },
+    );
+    runner.position = (position.x, position.y);
+    runner.velocity_y = velocity.y;
+    if runner.position.1 > FALL_LIMIT {
+        return None;
+    }
+
+    // The ground he could have touched this tick, checked first as in the
+    // game.
+    let ground: Vec<Collider> = ground_boxes(
+        from.0 + HITBOX.x().min(SLIDE_HITBOX.x()),
+        runner.position.0 + HITBOX.right().max(SLIDE_HITBOX.right()),
+        pits,
+    )
+    .into_iter()
+    .map(|bounding_box| Collider::Platform {
+        bounding_boxes: vec![bounding_box],
+        bob: None,
+    })
+    .collect();
+    for collider in ground.iter().chain(colliders.iter().copied()) {
+        let hitbox = runner.hitbox();
+        let motion = Point {
+            x: runner.position.0 - from.0,
+            y: runner.position.1 - from.1,
+        };
+        let contact = match collider {
+            Collider::Platform {
+                bounding_boxes,
+                bob: None,
+            } => platform_contact(&[hitbox], motion, bounding_boxes),
+            Collider::Platform {
+                bounding_boxes,
+                bob: Some((height, period)),
+            } => {
+                let offset = bob_offset(runner.position.0, *height, *period);
+                let bounding_boxes: Vec<Rect> = bounding_boxes
+                    .iter()
+                    .map(|bounding_box| {
+                        let mut bounding_box = *bounding_box;
+                        bounding_box.position.y += offset;
+                        bounding_box
+                    })
+                    .collect();
+                platform_contact(&[hitbox], motion, &bounding_boxes)
+            }
+            Collider::Barrier(bounding_box) => barrier_contact(&[hitbox], motion, bounding_box),
+            Collider::Overhead(bounding_box) => {
+                if is_beneath(&[hitbox], motion, bounding_box) {
+                    runner.sliding = runner.sliding.map(|frame| frame.min(SLIDING_FRAMES - 2));
+                }
+                barrier_contact(&[hitbox], motion, bounding_box)
+            }
+            Collider::RollingBarrier(bounding_box) => {
+                let mut bounding_box = *bounding_box;
+                bounding_box
+                    .set_x(bounding_box.x() - rolled_distance(runner.position.0, bounding_box.x()));
+                barrier_contact(&[hitbox], motion, &bounding_box)
+            }
+            Collider::Enemy { kind, home } => {
+                let position = Point {
+                    x: enemy_x(*kind, home.x, runner.position.0),
+                    y: home.y,
+                };
+                barrier_contact(&[hitbox], motion, &kind.hitbox(position))
+            }
+            Collider::Pit(_) => Contact::None,
+        };
+        match contact {
+            Contact::Land(top) => {
+                runner.position.1 = top - PLAYER_HEIGHT;
+                runner.jumping = false;
+            }
+            Contact::HitCeiling(depth) => {
+                runner.position.1 += depth;
+                runner.velocity_y = Fixed::ZERO;
+            }
+            Contact::KnockOut => return None,
+            Contact::None => {}
+        }
+    }
+    Some(runner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::ground::PIT_MIN_WIDTH;
+    use crate::game::segments::SegmentLibraryDefinition;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn layouts() -> SegmentLayouts {
+        let definition: SegmentLibraryDefinition =
+            serde_json::from_str(include_str!("../../static/segments.json")).unwrap();
+        SegmentLayouts::new(
+            definition,
+            |image| match image {
+                "stone" => Some((Fixed::from_int(90), Fixed::from_int(54))),
+                _ => None,
+            },
+            |_| true,
+        )
+        .unwrap()
+    }
+
+    fn rightmost(colliders: &[Collider]) -> Fixed {
+        colliders
+            .iter()
+            .map(|collider| collider.right())
+            .max()
+            .unwrap()
+    }
+
+    fn clearable(difficulty: &DifficultyCurve, colliders: &[Collider]) -> bool {
+        explore(difficulty, &[Runner::run_up(colliders)], &[], colliders).is_some()
+    }
+
+    fn difficulty() -> DifficultyCurve {
+        serde_json::from_str(include_str!("../../static/difficulty.json")).unwrap()
+    }
+
+    // Generates five segments after the start from each seed, checking every
+    // one can be cleared, and checks the seams were dug into pits about as
+    // often as the curve asks.
+    fn generate_from_seeds(seeds: std::ops::Range<u64>) {
+        let layouts = layouts();
+        let difficulty = difficulty();
+        let mut pits = 0;
+        let placed = (seeds.end - seeds.start) as usize * 5;
+        for seed in seeds {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let offset = Fixed::from_int(rng.gen_range(0..40000));
+            let mut previous = Placement::first(&layouts, layouts.start(), offset);
+            assert!(clearable(&difficulty, &previous.colliders));
+            for _ in 0..5 {
+                let timeline = rightmost(&previous.colliders);
+                previous = place_next_segment(
+                    &layouts,
+                    &difficulty,
+                    timeline,
+                    &previous,
+                    timeline,
+                    &mut rng,
+                )
+                .unwrap_or_else(|| panic!("seed {} found no clearable segment", seed));
+                assert!(!previous.arrivals.is_empty());
+                pits += usize::from(previous.pit.is_some());
+            }
+        }
+        // Rerolling an unclearable segment rerolls its pit too, so the odds
+        // are only roughly what the curve asks for.
+        let rate = pits as f64 / placed as f64;
+        assert!(
+            (rate - difficulty.pit_chance).abs() < 0.05,
+            "{} of {} seams dug into pits",
+            pits,
+            placed
+        );
+    }
+
+    #[test]
+    fn every_generated_segment_can_be_cleared() {
+        generate_from_seeds(0..100);
+    }
+
+    // cargo test --release generated_segments -- --ignored
+    #[test]
+    #[ignore]
+    fn thousands_of_generated_segments_can_be_cleared() {
+        generate_from_seeds(0..2000);
+    }
+
+    #[test]
+    fn every_seam_is_dug_into_a_pit_when_the_curve_always_asks_for_one() {
+        let layouts = layouts();
+        // Only the narrowest pits, so one can always be fitted in front of
+        // any segment.
+        let difficulty = DifficultyCurve {
+            pit_chance: 1.0,
+            max_pit_width: PIT_MIN_WIDTH,
+            ..difficulty()
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut previous = Placement::first(&layouts, layouts.start(), Fixed::ZERO);
+        for _ in 0..10 {
+            let timeline = rightmost(&previous.colliders);
+            let next = place_next_segment(
+                &layouts,
+                &difficulty,
+                timeline,
+                &previous,
+                timeline,
+                &mut rng,
+            )
+            .expect("no clearable segment found");
+            let pit = next.pit.expect("seam wasn't dug");
+            assert!(pit.left > timeline && pit.right < next.offset);
+            assert!(next
+                .colliders
+                .iter()
+                .any(|collider| matches!(collider, Collider::Pit(dug) if *dug == pit)));
+            previous = next;
+        }
+    }
+
+    #[test]
+    fn a_ceiling_too_long_to_jump_past_is_slid_under() {
+        let ceiling = [Collider::Overhead(Rect::from_pixels(400, 394, 2000, 118))];
+        assert!(clearable(&difficulty(), &ceiling));
+    }
+
+    #[test]
+    fn only_a_sliding_runner_gets_under_an_overhead() {
+        let overhead = Collider::Overhead(Rect::from_pixels(400, 394, 384, 118));
+        let under = Runner {
+            position: (Fixed::from_int(400), GROUND_TOP - PLAYER_HEIGHT),
+            velocity_y: Fixed::ZERO,
+            jumping: false,
+            sliding: None,
+        };
+        let difficulty = difficulty();
+        assert!(step(&difficulty, &[&overhead], &[], under, Move::Run).is_none());
+        let slid = step(&difficulty, &[&overhead], &[], under, Move::Slide).unwrap();
+        assert!(slid.sliding.is_some());
+    }
+
+    #[test]
+    fn a_pit_has_to_be_jumped() {
+        let pit = [Collider::Pit(Pit {
+            left: Fixed::from_int(400),
+            right: Fixed::from_int(700),
+        })];
+        assert!(clearable(&difficulty(), &pit));
+        let chasm = [Collider::Pit(Pit {
+            left: Fixed::from_int(400),
+            right: Fixed::from_int(2000),
+        })];
+        assert!(!clearable(&difficulty(), &chasm));
+    }
+
+    #[test]
+    fn jumping_into_a_platform_from_below_bumps_his_head_and_carries_on() {
+        let ceiling = Collider::Platform {
+            bounding_boxes: vec![Rect::from_pixels(0, 440, 1000, 40)],
+            bob: None,
+        };
+        let runner = Runner {
+            position: (Fixed::from_int(100), GROUND_TOP - PLAYER_HEIGHT),
+            velocity_y: Fixed::ZERO,
+            jumping: false,
+            sliding: None,
+        };
+        let bumped = step(&difficulty(), &[&ceiling], &[], runner, Move::Jump).unwrap();
+        assert!(bumped.jumping);
+        assert_eq!(bumped.velocity_y, Fixed::ZERO);
+        assert_eq!(bumped.position.1 + HITBOX.y(), Fixed::from_int(480));
+    }
+
+    #[test]
+    fn a_wall_taller_than_any_jump_is_rejected() {
+        let wall = [Collider::Barrier(Rect::from_pixels(400, 0, 90, 600))];
+        assert!(!clearable(&difficulty(), &wall));
+    }
+
+    #[test]
+    fn a_single_stone_can_be_jumped() {
+        let stone = [Collider::Barrier(Rect::from_pixels(400, 546, 90, 54))];
+        assert!(clearable(&difficulty(), &stone));
+    }
+}
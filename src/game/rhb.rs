@@ -1,48 +1,81 @@
 use self::red_hat_boy_states::*;
-use crate::engine::{Audio, Cell, Rect, Renderer, Sheet, Sound};
-use web_sys::HtmlImageElement;
+use super::ground::FALL_LIMIT;
+use crate::engine::{Audio, Cell, Fixed, Point, Rect, Sound, Sprite, SpriteSheet};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+const PLACEHOLDER_WIDTH: Fixed = Fixed::from_int(160);
+const PLACEHOLDER_HEIGHT: Fixed = Fixed::from_int(136);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PowerUp {
+    Shield,
+    Magnet,
+    DoubleJump,
+}
+
+// The boy's hitboxes for each frame of rhb.json, authored alongside it in
+// rhb_hitboxes.json and relative to his position. Every frame's boxes reach
+// down to the line he lands on, PLAYER_HEIGHT below his position, and no
+// further.
+pub type Hitboxes = HashMap<String, Vec<Rect>>;
 
 pub struct RedHatBoy {
     state: RedHatBoyStateMachine,
-    sprite_sheet: Sheet,
-    image: HtmlImageElement,
+    sprite_sheet: Rc<SpriteSheet>,
+    hitboxes: Rc<Hitboxes>,
+}
+// Only his state machine changes during a run; the sprite sheet and
+// hitboxes are fixed.
+impl Hash for RedHatBoy {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.state.hash(state);
+    }
 }
 impl RedHatBoy {
-    pub fn new(sheet: Sheet, image: HtmlImageElement, audio: Audio, jump_sound: Sound) -> Self {
+    pub fn new(
+        sprite_sheet: Rc<SpriteSheet>,
+        hitboxes: Rc<Hitboxes>,
+        audio: Audio,
+        jump_sound: Sound,
+        fall_sound: Sound,
+    ) -> Self {
         RedHatBoy {
-            state: RedHatBoyStateMachine::Idle(RedHatBoyState::new(audio, jump_sound)),
-            sprite_sheet: sheet,
-            image,
+            state: RedHatBoyStateMachine::Idle(RedHatBoyState::new(audio, jump_sound, fall_sound)),
+            sprite_sheet,
+            hitboxes,
         }
     }
 
-    pub fn pos_y(&self) -> i16 {
-        self.state.context().position.y
+    pub fn position(&self) -> Point {
+        self.state.context().position
     }
-    pub fn velocity_y(&self) -> i16 {
-        self.state.context().velocity.y
+    // Out of the run: knocked down, or gone down a pit.
+    pub fn is_out(&self) -> bool {
+        matches!(
+            self.state,
+            RedHatBoyStateMachine::Falling(_)
+                | RedHatBoyStateMachine::KnockedOut(_)
+                | RedHatBoyStateMachine::Plummeting(_)
+        )
     }
-    pub fn walking_speed(&self) -> i16 {
-        self.state.context().velocity.x
+    pub fn is_sliding(&self) -> bool {
+        matches!(self.state, RedHatBoyStateMachine::Sliding(_))
     }
-    pub fn draw(&self, renderer: &Renderer) {
-        let sprite = self.current_sprite().expect("Cell not found");
-
-        renderer.draw_image(
-            &self.image,
-            &Rect::new_from_x_y(
-                sprite.frame.x,
-                sprite.frame.y,
-                sprite.frame.w,
-                sprite.frame.h,
-            ),
-            &self.destination_box(),
-        );
+    // Ticks left on a power-up, zero when it isn't active.
+    pub fn power_up_remaining(&self, power_up: PowerUp) -> u32 {
+        self.state.context().power_ups.remaining(power_up)
     }
-    pub fn draw_bounding_box(&self, renderer: &Renderer) {
-        renderer.draw_rect(&self.bounding_box())
+    pub fn sprite(&self) -> Sprite {
+        Sprite::Frame {
+            sheet: self.sprite_sheet.clone(),
+            name: self.frame_name(),
+        }
     }
-    fn frame_name(&self) -> String {
+    pub fn frame_name(&self) -> String {
         format!(
             "{} ({}).png",
             self.state.frame_name(),
@@ -50,56 +83,95 @@ impl RedHatBoy {
         )
     }
     fn current_sprite(&self) -> Option<&Cell> {
-        self.sprite_sheet.frames.get(&self.frame_name())
+        self.sprite_sheet.cell(&self.frame_name())
     }
 
     pub fn destination_box(&self) -> Rect {
-        let sprite = self.current_sprite().expect("Cell not found");
+        let position = self.state.context().position;
 
-        Rect::new_from_x_y(
-            self.state.context().position.x + sprite.sprite_source_size.x,
-            self.state.context().position.y + sprite.sprite_source_size.y,
-            sprite.frame.w,
-            sprite.frame.h,
-        )
+        match self.current_sprite() {
+            Some(sprite) => Rect::new_from_x_y(
+                position.x + sprite.sprite_source_size.x.into(),
+                position.y + sprite.sprite_source_size.y.into(),
+                sprite.frame.w.into(),
+                sprite.frame.h.into(),
+            ),
+            None => Rect::new(position, PLACEHOLDER_WIDTH, PLACEHOLDER_HEIGHT),
+        }
     }
 
-    pub fn bounding_box(&self) -> Rect {
-        const X_OFFSET: i16 = 18;
-        const Y_OFFSET: i16 = 14;
-        const WIDTH_OFFSET: i16 = 28;
+    // Relative to his position. A frame nobody has authored boxes for falls
+    // back on a box a little inside the sprite.
+    pub fn hitboxes(&self) -> Vec<Rect> {
+        const X_OFFSET: Fixed = Fixed::from_int(18);
+        const Y_OFFSET: Fixed = Fixed::from_int(14);
+        const WIDTH_OFFSET: Fixed = Fixed::from_int(28);
+        if let Some(hitboxes) = self.hitboxes.get(&self.frame_name()) {
+            return hitboxes.clone();
+        }
+        let position = self.state.context().position;
         let mut bounding_box = self.destination_box();
-        bounding_box.set_x(bounding_box.x() + X_OFFSET);
+        bounding_box.set_x(bounding_box.x() - position.x + X_OFFSET);
         bounding_box.width -= WIDTH_OFFSET;
-        bounding_box.position.y += Y_OFFSET;
-        bounding_box.height -= Y_OFFSET;
-        bounding_box
+        bounding_box.position.y += Y_OFFSET - position.y;
+        bounding_box.height = PLAYER_HEIGHT - bounding_box.y();
+        vec![bounding_box]
+    }
+
+    pub fn bounding_boxes(&self) -> Vec<Rect> {
+        let position = self.state.context().position;
+        self.hitboxes()
+            .iter()
+            .map(|hitbox| {
+                Rect::new_from_x_y(
+                    position.x + hitbox.x(),
+                    position.y + hitbox.y(),
+                    hitbox.width,
+                    hitbox.height,
+                )
+            })
+            .collect()
     }
 
     pub fn update(&mut self) {
         self.state = self.state.clone().update();
     }
+    pub fn set_running_speed(&mut self, speed: Fixed) {
+        self.state = self.state.clone().set_running_speed(speed);
+    }
     pub fn run_right(&mut self) {
         self.state = self.state.clone().transition(Event::Run);
     }
     pub fn slide(&mut self) {
         self.state = self.state.clone().transition(Event::Slide);
     }
+    pub fn stay_down(&mut self) {
+        self.state = self.state.clone().transition(Event::StayDown);
+    }
     pub fn jump(&mut self) {
         self.state = self.state.clone().transition(Event::Jump);
     }
+    pub fn double_jump(&mut self) {
+        self.state = self.state.clone().transition(Event::DoubleJump);
+    }
+    pub fn bounce(&mut self) {
+        self.state = self.state.clone().transition(Event::Bounce);
+    }
+    pub fn power_up(&mut self, power_up: PowerUp) {
+        self.state = self.state.clone().transition(Event::PowerUp(power_up));
+    }
     pub fn knock_out(&mut self) {
         self.state = self.state.clone().transition(Event::KnockOut);
     }
-    pub fn land_on(&mut self, position: i16) {
+    pub fn land_on(&mut self, position: Fixed) {
         self.state = self.state.clone().transition(Event::Land(position))
     }
-    pub fn hit_ceiling(&mut self) {
-        self.state = self.state.clone().transition(Event::HitCeiling);
+    pub fn hit_ceiling(&mut self, depth: Fixed) {
+        self.state = self.state.clone().transition(Event::HitCeiling(depth));
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 enum RedHatBoyStateMachine {
     Idle(RedHatBoyState<Idle>),
     Running(RedHatBoyState<Running>),
@@ -107,15 +179,21 @@ enum RedHatBoyStateMachine {
     Sliding(RedHatBoyState<Sliding>),
     Falling(RedHatBoyState<Falling>),
     KnockedOut(RedHatBoyState<KnockedOut>),
+    Plummeting(RedHatBoyState<Plummeting>),
 }
 
 pub enum Event {
     Run,
     Jump,
-    Land(i16),
-    HitCeiling,
+    DoubleJump,
+    Bounce,
+    Land(Fixed),
+    HitCeiling(Fixed),
     Slide,
+    StayDown,
     KnockOut,
+    FallOff,
+    PowerUp(PowerUp),
     Update,
 }
 
@@ -124,8 +202,12 @@ impl RedHatBoyStateMachine {
         match (self.clone(), event) {
             (RedHatBoyStateMachine::Idle(state), Event::Run) => state.run().into(),
             (RedHatBoyStateMachine::Running(state), Event::Slide) => state.slide().into(),
+            (RedHatBoyStateMachine::Sliding(state), Event::StayDown) => state.stay_down().into(),
             (RedHatBoyStateMachine::Running(state), Event::Jump) => state.jump().into(),
 
+            (RedHatBoyStateMachine::Idle(state), Event::Land(position)) => {
+                state.land_on(position).into()
+            }
             (RedHatBoyStateMachine::Jumping(state), Event::Land(position)) => {
                 state.land_on(position).into()
             }
@@ -142,8 +224,17 @@ impl RedHatBoyStateMachine {
                 state.land_on(position).into()
             }
 
-            (RedHatBoyStateMachine::Jumping(state), Event::HitCeiling) => {
-                state.hit_ceiling().into()
+            (RedHatBoyStateMachine::Jumping(state), Event::DoubleJump)
+                if state.context().power_ups.can_double_jump() =>
+            {
+                state.double_jump().into()
+            }
+
+            (RedHatBoyStateMachine::Running(state), Event::Bounce) => state.bounce().into(),
+            (RedHatBoyStateMachine::Jumping(state), Event::Bounce) => state.bounce().into(),
+
+            (RedHatBoyStateMachine::Jumping(state), Event::HitCeiling(depth)) => {
+                state.hit_ceiling(depth).into()
             }
 
             (RedHatBoyStateMachine::Idle(state), Event::Update) => state.update().into(),
@@ -152,10 +243,46 @@ impl RedHatBoyStateMachine {
             (RedHatBoyStateMachine::Jumping(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Falling(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::KnockedOut(state), Event::Update) => state.update().into(),
+            (RedHatBoyStateMachine::Plummeting(state), Event::Update) => state.update().into(),
 
+            (RedHatBoyStateMachine::Running(state), Event::KnockOut)
+                if state.context().power_ups.is_shielded() =>
+            {
+                state.absorb_hit().into()
+            }
+            (RedHatBoyStateMachine::Jumping(state), Event::KnockOut)
+                if state.context().power_ups.is_shielded() =>
+            {
+                state.absorb_hit().into()
+            }
+            (RedHatBoyStateMachine::Sliding(state), Event::KnockOut)
+                if state.context().power_ups.is_shielded() =>
+            {
+                state.absorb_hit().into()
+            }
             (RedHatBoyStateMachine::Running(state), Event::KnockOut) => state.knock_out().into(),
             (RedHatBoyStateMachine::Jumping(state), Event::KnockOut) => state.knock_out().into(),
             (RedHatBoyStateMachine::Sliding(state), Event::KnockOut) => state.knock_out().into(),
+
+            // No shield saves him from a pit.
+            (RedHatBoyStateMachine::Running(state), Event::FallOff) => state.fall_off().into(),
+            (RedHatBoyStateMachine::Jumping(state), Event::FallOff) => state.fall_off().into(),
+            (RedHatBoyStateMachine::Sliding(state), Event::FallOff) => state.fall_off().into(),
+            (RedHatBoyStateMachine::Falling(state), Event::FallOff) => state.fall_off().into(),
+            (RedHatBoyStateMachine::KnockedOut(state), Event::FallOff) => state.fall_off().into(),
+
+            (RedHatBoyStateMachine::Idle(state), Event::PowerUp(power_up)) => {
+                state.power_up(power_up).into()
+            }
+            (RedHatBoyStateMachine::Running(state), Event::PowerUp(power_up)) => {
+                state.power_up(power_up).into()
+            }
+            (RedHatBoyStateMachine::Sliding(state), Event::PowerUp(power_up)) => {
+                state.power_up(power_up).into()
+            }
+            (RedHatBoyStateMachine::Jumping(state), Event::PowerUp(power_up)) => {
+                state.power_up(power_up).into()
+            }
             _ => self,
         }
     }
@@ -168,6 +295,7 @@ impl RedHatBoyStateMachine {
             RedHatBoyStateMachine::Jumping(state) => state.frame_name(),
             RedHatBoyStateMachine::Falling(state) => state.frame_name(),
             RedHatBoyStateMachine::KnockedOut(state) => state.frame_name(),
+            RedHatBoyStateMachine::Plummeting(state) => state.frame_name(),
         }
     }
 
@@ -179,10 +307,29 @@ impl RedHatBoyStateMachine {
             RedHatBoyStateMachine::Jumping(state) => state.context(),
             RedHatBoyStateMachine::Falling(state) => state.context(),
             RedHatBoyStateMachine::KnockedOut(state) => state.context(),
+            RedHatBoyStateMachine::Plummeting(state) => state.context(),
         }
     }
     fn update(self) -> Self {
-        self.transition(Event::Update)
+        let state = self.transition(Event::Update);
+        // Nothing is left to catch him once he has dropped out of sight.
+        if state.context().position.y > FALL_LIMIT {
+            state.transition(Event::FallOff)
+        } else {
+            state
+        }
+    }
+
+    fn set_running_speed(self, speed: Fixed) -> Self {
+        match self {
+            RedHatBoyStateMachine::Idle(state) => state.set_running_speed(speed).into(),
+            RedHatBoyStateMachine::Running(state) => state.set_running_speed(speed).into(),
+            RedHatBoyStateMachine::Sliding(state) => state.set_running_speed(speed).into(),
+            RedHatBoyStateMachine::Jumping(state) => state.set_running_speed(speed).into(),
+            RedHatBoyStateMachine::Falling(state) => state.set_running_speed(speed).into(),
+            RedHatBoyStateMachine::KnockedOut(state) => state.set_running_speed(speed).into(),
+            RedHatBoyStateMachine::Plummeting(state) => state.set_running_speed(speed).into(),
+        }
     }
 }
 
@@ -222,6 +369,12 @@ impl From<RedHatBoyState<KnockedOut>> for RedHatBoyStateMachine {
     }
 }
 
+impl From<RedHatBoyState<Plummeting>> for RedHatBoyStateMachine {
+    fn from(state: RedHatBoyState<Plummeting>) -> Self {
+        RedHatBoyStateMachine::Plummeting(state)
+    }
+}
+
 impl From<SlidingEndState> for RedHatBoyStateMachine {
     fn from(end_state: SlidingEndState) -> Self {
         match end_state {
@@ -231,15 +384,6 @@ impl From<SlidingEndState> for RedHatBoyStateMachine {
     }
 }
 
-impl From<JumpingEndState> for RedHatBoyStateMachine {
-    fn from(end_state: JumpingEndState) -> Self {
-        match end_state {
-            JumpingEndState::Complete(running_state) => running_state.into(),
-            JumpingEndState::Jumping(jumping_state) => jumping_state.into(),
-        }
-    }
-}
-
 impl From<FallingEndState> for RedHatBoyStateMachine {
     fn from(end_state: FallingEndState) -> Self {
         match end_state {
@@ -250,41 +394,127 @@ impl From<FallingEndState> for RedHatBoyStateMachine {
 }
 
 pub mod red_hat_boy_states {
-    use super::super::HEIGHT;
+    use super::super::ground::GROUND_TOP;
     use super::*;
-    use crate::engine::Point;
 
-    pub const FLOOR: i16 = 479;
-    const PLAYER_HEIGHT: i16 = HEIGHT - FLOOR;
-    const STARTING_POINT: i16 = -20;
+    // From his position down to the soles of his feet.
+    pub const PLAYER_HEIGHT: Fixed = Fixed::from_int(121);
+    const STARTING_POINT: Fixed = Fixed::from_int(-20);
     const IDLE_FRAME_NAME: &str = "Idle";
     const RUN_FRAME_NAME: &str = "Run";
     const IDLE_FRAMES: u8 = 29;
-    const RUNNING_FRAMES: u8 = 23;
-    const RUNNING_SPEED: i16 = 4;
+    pub const RUNNING_FRAMES: u8 = 23;
+    const RUNNING_SPEED: Fixed = Fixed::from_int(4);
     const SLIDING_FRAME_NAME: &str = "Slide";
-    const SLIDING_FRAMES: u8 = 14;
+    pub const SLIDING_FRAMES: u8 = 14;
     const JUMPING_FRAME_NAME: &str = "Jump";
     const JUMPING_FRAMES: u8 = 35;
-    const JUMP_SPEED: i16 = -25;
-    const GRAVITY: i16 = 1;
-    const TERMINAL_VELOCITY: i16 = 20;
-    const FALLING_FRAMES: u8 = 29;
+    pub const JUMP_SPEED: Fixed = Fixed::from_int(-25);
+    const BOUNCE_SPEED: Fixed = Fixed::from_int(-15);
+    const GRAVITY: Fixed = Fixed::from_int(1);
+    const TERMINAL_VELOCITY: Fixed = Fixed::from_int(20);
+    pub const FALLING_FRAMES: u8 = 29;
     const FALLING_FRAME_NAME: &str = "Dead";
+    pub const PLUMMETING_FRAMES: u8 = 23;
+    const PLUMMETING_FRAME_NAME: &str = "Hurt";
+    // Dropping out of sight, he hops back up into view before falling away.
+    const PLUMMETING_HOP_SPEED: Fixed = Fixed::from_int(-18);
+    const SHIELD_TICKS: u32 = 600;
+    const MAGNET_TICKS: u32 = 480;
+    const DOUBLE_JUMP_TICKS: u32 = 600;
+    // The obstacle a shield saved the boy from still overlaps him for a few
+    // ticks, so every hit in this window after the first is ignored too.
+    const SHIELD_GRACE_TICKS: u32 = 45;
 
-    #[derive(Clone)]
+    #[derive(Clone, Hash)]
     pub struct RedHatBoyState<S> {
         context: RedHatBoyContext,
         _state: S,
     }
 
+    // One tick of gravity and movement, shared with the reachability checker
+    // so generated segments are validated against the arc the boy really flies.
+    pub fn physics_step(mut position: Point, mut velocity: Point) -> (Point, Point) {
+        if velocity.y < TERMINAL_VELOCITY {
+            velocity.y += GRAVITY;
+        }
+
+        position.x += velocity.x;
+        position.y += velocity.y;
+        (position, velocity)
+    }
+
+    // Ticks left on each power-up; they count down with every update.
+    #[derive(Clone, Copy, Default, Hash)]
+    pub struct PowerUps {
+        shield: u32,
+        magnet: u32,
+        double_jump: u32,
+        grace: u32,
+        double_jumped: bool,
+    }
+    impl PowerUps {
+        pub fn remaining(&self, power_up: PowerUp) -> u32 {
+            match power_up {
+                PowerUp::Shield => self.shield,
+                PowerUp::Magnet => self.magnet,
+                PowerUp::DoubleJump => self.double_jump,
+            }
+        }
+
+        pub fn is_shielded(&self) -> bool {
+            self.shield > 0 || self.grace > 0
+        }
+
+        pub fn can_double_jump(&self) -> bool {
+            self.double_jump > 0 && !self.double_jumped
+        }
+
+        fn grant(mut self, power_up: PowerUp) -> Self {
+            match power_up {
+                PowerUp::Shield => self.shield = SHIELD_TICKS,
+                PowerUp::Magnet => self.magnet = MAGNET_TICKS,
+                PowerUp::DoubleJump => self.double_jump = DOUBLE_JUMP_TICKS,
+            }
+            self
+        }
+
+        fn absorb(mut self) -> Self {
+            if self.grace == 0 {
+                self.shield = 0;
+                self.grace = SHIELD_GRACE_TICKS;
+            }
+            self
+        }
+
+        fn tick(mut self) -> Self {
+            self.shield = self.shield.saturating_sub(1);
+            self.magnet = self.magnet.saturating_sub(1);
+            self.double_jump = self.double_jump.saturating_sub(1);
+            self.grace = self.grace.saturating_sub(1);
+            self
+        }
+    }
+
     #[derive(Clone)]
     pub struct RedHatBoyContext {
         pub frame: u8,
         pub position: Point,
         pub velocity: Point,
+        pub power_ups: PowerUps,
         audio: Audio,
         jump_sound: Sound,
+        fall_sound: Sound,
+    }
+
+    // His sounds are only played, never read back, so they stay out.
+    impl Hash for RedHatBoyContext {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.frame.hash(state);
+            self.position.hash(state);
+            self.velocity.hash(state);
+            self.power_ups.hash(state);
+        }
     }
 
     impl RedHatBoyContext {
@@ -294,20 +524,15 @@ pub mod red_hat_boy_states {
             } else {
                 self.frame = 0;
             }
+            self.power_ups = self.power_ups.tick();
 
             self.physics_update()
         }
 
         pub fn physics_update(mut self) -> Self {
-            if self.velocity.y < TERMINAL_VELOCITY {
-                self.velocity.y += GRAVITY;
-            }
-
-            self.position.y += self.velocity.y;
-
-            if self.position.y > FLOOR {
-                self.position.y = FLOOR;
-            }
+            let (position, velocity) = physics_step(self.position, self.velocity);
+            self.position = position;
+            self.velocity = velocity;
             self
         }
 
@@ -321,21 +546,35 @@ pub mod red_hat_boy_states {
             self
         }
 
-        fn set_vertical_velocity(mut self, y: i16) -> Self {
+        // Only changes the speed of a boy who is already moving, so a stopped
+        // or knocked out boy stays put.
+        fn set_running_speed(mut self, speed: Fixed) -> Self {
+            if self.velocity.x > Fixed::ZERO {
+                self.velocity.x = speed;
+            }
+            self
+        }
+
+        fn set_vertical_velocity(mut self, y: Fixed) -> Self {
             self.velocity.y = y;
             self
         }
 
         fn stop(mut self) -> Self {
-            self.velocity.x = 0;
+            self.velocity.x = Fixed::ZERO;
             self
         }
         fn stop_y(mut self) -> Self {
-            self.velocity.y = 0;
+            self.velocity.y = Fixed::ZERO;
             self
         }
 
-        fn set_on(mut self, position: i16) -> Self {
+        fn drop_by(mut self, depth: Fixed) -> Self {
+            self.position.y += depth;
+            self
+        }
+
+        fn set_on(mut self, position: Fixed) -> Self {
             let position = position - PLAYER_HEIGHT;
             self.position.y = position;
             self
@@ -347,33 +586,44 @@ pub mod red_hat_boy_states {
             }
             self
         }
+
+        fn play_fall_sound(self) -> Self {
+            if let Err(err) = self.audio.play_sound(&self.fall_sound) {
+                log!("Error playing fall sound {:#?}", err);
+            }
+            self
+        }
     }
 
-    #[derive(Copy, Clone)]
+    #[derive(Copy, Clone, Hash)]
     pub struct Idle;
-    #[derive(Copy, Clone)]
+    #[derive(Copy, Clone, Hash)]
     pub struct Running;
-    #[derive(Copy, Clone)]
+    #[derive(Copy, Clone, Hash)]
     pub struct Sliding;
-    #[derive(Copy, Clone)]
+    #[derive(Copy, Clone, Hash)]
     pub struct Jumping;
-    #[derive(Copy, Clone)]
+    #[derive(Copy, Clone, Hash)]
     pub struct Falling;
-    #[derive(Copy, Clone)]
+    #[derive(Copy, Clone, Hash)]
     pub struct KnockedOut;
+    #[derive(Copy, Clone, Hash)]
+    pub struct Plummeting;
 
     impl RedHatBoyState<Idle> {
-        pub fn new(audio: Audio, jump_sound: Sound) -> Self {
+        pub fn new(audio: Audio, jump_sound: Sound, fall_sound: Sound) -> Self {
             RedHatBoyState {
                 context: RedHatBoyContext {
                     frame: 0,
                     position: Point {
                         x: STARTING_POINT,
-                        y: FLOOR,
+                        y: GROUND_TOP - PLAYER_HEIGHT,
                     },
-                    velocity: Point { x: 0, y: 0 },
+                    velocity: Point::default(),
+                    power_ups: PowerUps::default(),
                     audio,
                     jump_sound,
+                    fall_sound,
                 },
                 _state: Idle,
             }
@@ -391,6 +641,12 @@ pub mod red_hat_boy_states {
             self.context = self.context.update(IDLE_FRAMES);
             self
         }
+        pub fn land_on(self, position: Fixed) -> RedHatBoyState<Idle> {
+            Self {
+                context: self.context.set_on(position),
+                _state: Idle,
+            }
+        }
     }
 
     impl RedHatBoyState<Running> {
@@ -407,7 +663,8 @@ pub mod red_hat_boy_states {
                 _state: Sliding,
             }
         }
-        pub fn jump(self) -> RedHatBoyState<Jumping> {
+        pub fn jump(mut self) -> RedHatBoyState<Jumping> {
+            self.context.power_ups.double_jumped = false;
             RedHatBoyState {
                 context: self
                     .context
@@ -423,12 +680,21 @@ pub mod red_hat_boy_states {
                 _state: Falling,
             }
         }
-        pub fn land_on(self, position: i16) -> RedHatBoyState<Running> {
+        pub fn land_on(self, position: Fixed) -> RedHatBoyState<Running> {
             RedHatBoyState {
                 context: self.context.set_on(position),
                 _state: Running,
             }
         }
+        pub fn bounce(self) -> RedHatBoyState<Jumping> {
+            RedHatBoyState {
+                context: self
+                    .context
+                    .set_vertical_velocity(BOUNCE_SPEED)
+                    .reset_frame(),
+                _state: Jumping,
+            }
+        }
     }
 
     impl RedHatBoyState<Sliding> {
@@ -444,6 +710,12 @@ pub mod red_hat_boy_states {
                 SlidingEndState::Sliding(self)
             }
         }
+        // Under something low he keeps sliding on his last frame rather than
+        // standing up into it.
+        pub fn stay_down(mut self) -> RedHatBoyState<Sliding> {
+            self.context.frame = self.context.frame.min(SLIDING_FRAMES - 2);
+            self
+        }
         pub fn stand(self) -> RedHatBoyState<Running> {
             RedHatBoyState {
                 context: self.context.reset_frame(),
@@ -456,7 +728,7 @@ pub mod red_hat_boy_states {
                 _state: Falling,
             }
         }
-        pub fn land_on(self, position: i16) -> RedHatBoyState<Sliding> {
+        pub fn land_on(self, position: Fixed) -> RedHatBoyState<Sliding> {
             Self {
                 context: self.context.set_on(position),
                 _state: Sliding,
@@ -472,15 +744,11 @@ pub mod red_hat_boy_states {
         pub fn frame_name(&self) -> &str {
             JUMPING_FRAME_NAME
         }
-        pub fn update(mut self) -> JumpingEndState {
+        pub fn update(mut self) -> Self {
             self.context = self.context.update(JUMPING_FRAMES);
-            if self.context.position.y >= FLOOR {
-                JumpingEndState::Complete(self.land_on(HEIGHT))
-            } else {
-                JumpingEndState::Jumping(self)
-            }
+            self
         }
-        pub fn land_on(self, position: i16) -> RedHatBoyState<Running> {
+        pub fn land_on(self, position: Fixed) -> RedHatBoyState<Running> {
             RedHatBoyState {
                 context: self.context.reset_frame().set_on(position),
                 _state: Running,
@@ -492,16 +760,32 @@ pub mod red_hat_boy_states {
                 _state: Falling,
             }
         }
-        pub fn hit_ceiling(&self) -> RedHatBoyState<Falling> {
+        // Bumping his head on the underside of something ends the rise of
+        // his jump, and he drops from there.
+        pub fn hit_ceiling(self, depth: Fixed) -> RedHatBoyState<Jumping> {
             RedHatBoyState {
-                context: self.context.clone().reset_frame().stop().stop_y(),
-                _state: Falling,
+                context: self.context.drop_by(depth).stop_y(),
+                _state: Jumping,
+            }
+        }
+        // Springing off an enemy's head, a little lower than a full jump.
+        pub fn bounce(self) -> RedHatBoyState<Jumping> {
+            RedHatBoyState {
+                context: self.context.set_vertical_velocity(BOUNCE_SPEED),
+                _state: Jumping,
+            }
+        }
+        pub fn double_jump(mut self) -> RedHatBoyState<Jumping> {
+            self.context.power_ups.double_jumped = true;
+            RedHatBoyState {
+                context: self
+                    .context
+                    .set_vertical_velocity(JUMP_SPEED)
+                    .reset_frame()
+                    .play_jump_sound(),
+                _state: Jumping,
             }
         }
-    }
-    pub enum JumpingEndState {
-        Complete(RedHatBoyState<Running>),
-        Jumping(RedHatBoyState<Jumping>),
     }
 
     impl RedHatBoyState<Falling> {
@@ -523,7 +807,7 @@ pub mod red_hat_boy_states {
                 _state: KnockedOut,
             }
         }
-        pub fn land_on(self, position: i16) -> RedHatBoyState<Falling> {
+        pub fn land_on(self, position: Fixed) -> RedHatBoyState<Falling> {
             Self {
                 context: self.context.set_on(position),
                 _state: Falling,
@@ -544,7 +828,7 @@ pub mod red_hat_boy_states {
             self.context = self.context.physics_update();
             self
         }
-        pub fn land_on(self, position: i16) -> RedHatBoyState<KnockedOut> {
+        pub fn land_on(self, position: Fixed) -> RedHatBoyState<KnockedOut> {
             Self {
                 context: self.context.set_on(position),
                 _state: KnockedOut,
@@ -552,9 +836,205 @@ pub mod red_hat_boy_states {
         }
     }
 
+    impl RedHatBoyState<Plummeting> {
+        pub fn frame_name(&self) -> &str {
+            PLUMMETING_FRAME_NAME
+        }
+        // Plays through once and stays on the last frame as he drops away.
+        pub fn update(mut self) -> Self {
+            let frame = self.context.frame;
+            self.context = self.context.update(PLUMMETING_FRAMES);
+            self.context.frame = self.context.frame.max(frame);
+            self
+        }
+    }
+
     impl<S> RedHatBoyState<S> {
         pub fn context(&self) -> &RedHatBoyContext {
             &self.context
         }
+        pub fn fall_off(self) -> RedHatBoyState<Plummeting> {
+            RedHatBoyState {
+                context: self
+                    .context
+                    .reset_frame()
+                    .stop()
+                    .set_vertical_velocity(PLUMMETING_HOP_SPEED)
+                    .play_fall_sound(),
+                _state: Plummeting,
+            }
+        }
+        pub fn set_running_speed(mut self, speed: Fixed) -> Self {
+            self.context = self.context.set_running_speed(speed);
+            self
+        }
+        pub fn power_up(mut self, power_up: PowerUp) -> Self {
+            self.context.power_ups = self.context.power_ups.grant(power_up);
+            self
+        }
+        pub fn absorb_hit(mut self) -> Self {
+            self.context.power_ups = self.context.power_ups.absorb();
+            self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Sheet, Texture};
+    use crate::game::ground::GROUND_TOP;
+
+    fn boy(hitboxes: Hitboxes) -> RedHatBoy {
+        let sheet: Sheet = serde_json::from_str(include_str!("../../static/rhb.json")).unwrap();
+        let audio = Audio::headless();
+        let sound = audio.silence().unwrap();
+        RedHatBoy::new(
+            Rc::new(SpriteSheet::new(sheet, Texture::headless(0, 0))),
+            Rc::new(hitboxes),
+            audio,
+            sound.clone(),
+            sound,
+        )
+    }
+
+    fn authored() -> Hitboxes {
+        serde_json::from_str(include_str!("../../static/rhb_hitboxes.json")).unwrap()
+    }
+
+    fn top(hitboxes: &[Rect]) -> Fixed {
+        hitboxes.iter().map(Rect::y).min().unwrap()
+    }
+
+    // The frame he shows on each of the next `ticks` ticks on the ground,
+    // with the hitboxes looked up for it.
+    fn frames(boy: &mut RedHatBoy, ticks: usize) -> Vec<(String, Vec<Rect>)> {
+        (0..ticks)
+            .map(|_| {
+                let frame = (boy.frame_name(), boy.hitboxes());
+                boy.update();
+                boy.land_on(GROUND_TOP);
+                frame
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_slide_frame_is_lower_than_every_run_frame() {
+        let authored = authored();
+        let mut boy = boy(authored.clone());
+        boy.run_right();
+        let running = frames(&mut boy, usize::from(RUNNING_FRAMES) + 1);
+        boy.slide();
+        let sliding = frames(&mut boy, usize::from(SLIDING_FRAMES));
+        assert!(!boy.is_sliding());
+
+        for (name, hitboxes) in running.iter().chain(&sliding) {
+            assert!(authored.contains_key(name), "{} has no hitboxes", name);
+            assert_eq!(hitboxes.len(), authored[name].len());
+        }
+        assert!(running.iter().all(|(name, _)| name.starts_with("Run")));
+        assert!(sliding.iter().all(|(name, _)| name.starts_with("Slide")));
+        let highest_slide = sliding.iter().map(|(_, hitboxes)| top(hitboxes)).min();
+        let lowest_run = running.iter().map(|(_, hitboxes)| top(hitboxes)).max();
+        assert!(
+            highest_slide > lowest_run,
+            "a slide frame reaches {:?}, a run frame only {:?}",
+            highest_slide,
+            lowest_run
+        );
+    }
+
+    #[test]
+    fn a_frame_without_hitboxes_falls_back_on_a_box_inside_the_sprite() {
+        let mut hitboxes = authored();
+        hitboxes.remove("Idle (1).png");
+        let boy = boy(hitboxes);
+        assert_eq!(boy.frame_name(), "Idle (1).png");
+
+        let cell = boy.current_sprite().unwrap();
+        let fallback = boy.hitboxes();
+        assert_eq!(fallback.len(), 1);
+        let hitbox = fallback[0];
+        let trim = &cell.sprite_source_size;
+        assert_eq!(hitbox.x(), Fixed::from_int(i32::from(trim.x) + 18));
+        assert_eq!(hitbox.y(), Fixed::from_int(i32::from(trim.y) + 14));
+        assert_eq!(hitbox.width, Fixed::from_int(i32::from(cell.frame.w) - 28));
+        assert_eq!(hitbox.y() + hitbox.height, PLAYER_HEIGHT);
+    }
+
+    fn running() -> RedHatBoyStateMachine {
+        let audio = Audio::headless();
+        let sound = audio.silence().unwrap();
+        RedHatBoyStateMachine::Idle(RedHatBoyState::new(audio, sound.clone(), sound))
+            .transition(Event::Run)
+    }
+
+    // Steps him with no ground under him until he drops out of sight, and
+    // returns the state he was in on the tick before along with the one he
+    // ends up in.
+    fn drop_down_a_pit(
+        mut boy: RedHatBoyStateMachine,
+    ) -> (RedHatBoyStateMachine, RedHatBoyStateMachine) {
+        for _ in 0..100 {
+            let next = boy.clone().update();
+            if let RedHatBoyStateMachine::Plummeting(_) = next {
+                assert!(boy.context().position.y <= FALL_LIMIT);
+                return (boy, next);
+            }
+            boy = next;
+        }
+        panic!("never dropped past the fall limit");
+    }
+
+    // A few ticks into the pit, still in sight.
+    fn falling_in(mut boy: RedHatBoyStateMachine) -> RedHatBoyStateMachine {
+        for _ in 0..10 {
+            boy = boy.update();
+        }
+        boy
+    }
+
+    #[test]
+    fn dropping_past_the_fall_limit_plummets_from_any_state_he_is_in() {
+        let (before, _) = drop_down_a_pit(running());
+        assert!(matches!(before, RedHatBoyStateMachine::Running(_)));
+
+        let (before, _) = drop_down_a_pit(running().transition(Event::Jump));
+        assert!(matches!(before, RedHatBoyStateMachine::Jumping(_)));
+
+        let (before, _) = drop_down_a_pit(falling_in(running()).transition(Event::Slide));
+        assert!(matches!(before, RedHatBoyStateMachine::Sliding(_)));
+
+        let (before, _) = drop_down_a_pit(running().transition(Event::KnockOut));
+        assert!(matches!(before, RedHatBoyStateMachine::Falling(_)));
+
+        // Knocked down on solid ground, which then isn't there.
+        let mut boy = running().transition(Event::KnockOut);
+        for _ in 0..FALLING_FRAMES {
+            boy = boy.update().transition(Event::Land(GROUND_TOP));
+        }
+        let (before, _) = drop_down_a_pit(boy);
+        assert!(matches!(before, RedHatBoyStateMachine::KnockedOut(_)));
+    }
+
+    #[test]
+    fn no_shield_saves_him_from_a_pit() {
+        let shielded = running().transition(Event::PowerUp(PowerUp::Shield));
+        let (_, after) = drop_down_a_pit(shielded);
+        assert!(matches!(after, RedHatBoyStateMachine::Plummeting(_)));
+    }
+
+    #[test]
+    fn plummeting_plays_through_once_and_stays_on_its_last_frame() {
+        let (_, mut boy) = drop_down_a_pit(running());
+        assert_eq!(boy.context().frame, 0);
+        assert_eq!(boy.frame_name(), "Hurt");
+        for tick in 1..=u32::from(PLUMMETING_FRAMES) * 3 {
+            boy = boy.update();
+            let expected = tick.min(u32::from(PLUMMETING_FRAMES));
+            assert_eq!(u32::from(boy.context().frame), expected);
+            assert!(matches!(boy, RedHatBoyStateMachine::Plummeting(_)));
+        }
     }
 }
@@ -1,109 +1,670 @@
-use crate::engine::{Image, Point, Rect, SpriteSheet};
-use crate::game::obstacles::{Barrier, Obstacle, Platform};
+use crate::engine::{
+    AssetError, AssetKind, Assets, Entity, Fixed, Point, Rect, SpriteSheet, Texture,
+};
+use crate::game::difficulty::SegmentRating;
+use crate::game::enemies::{spawn_enemy, EnemyKind};
+use crate::game::ground::{Ground, Pit, PIT_MIN_WIDTH};
+use crate::game::obstacles::{
+    spawn_barrier, spawn_collectible, spawn_overhead, spawn_platform, Pickup, PlatformMotion,
+    ROLL_LIMIT,
+};
+use crate::game::reachability::Collider;
+use crate::game::rhb::PowerUp;
+use crate::game::world::GameWorld;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::rc::Rc;
-use web_sys::HtmlImageElement;
-
-const LOW_PLATFORM: i16 = 420;
-const HIGH_PLATFORM: i16 = 375;
-const INITIAL_OBSTACLE_OFFSET: i16 = 150;
-const FIRST_PLATFORM: i16 = 400;
-const STONE_HEIGHT: i16 = 54;
-const STONE_ON_GROUND: i16 = 546;
-const STONE_ON_LOW_PLATFORM: i16 = LOW_PLATFORM - STONE_HEIGHT;
-
-const FLOATING_PLATFORM_SPRITES: [&str; 3] = ["13.png", "14.png", "15.png"];
-const FLOATING_PLATFORM_BOUNDING_BOXES: [Rect; 3] = [
-    Rect {
-        position: Point { x: 0, y: 0 },
-        width: 60,
-        height: 54,
+
+#[derive(Deserialize, Serialize)]
+pub struct SegmentLibraryDefinition {
+    pub start: String,
+    #[serde(default)]
+    pub platforms: HashMap<String, PlatformDefinition>,
+    #[serde(default)]
+    pub collectibles: HashMap<String, CollectibleDefinition>,
+    pub segments: Vec<SegmentDefinition>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlatformDefinition {
+    pub sprites: Vec<String>,
+    pub bounding_boxes: Vec<Rect>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectibleDefinition {
+    pub sprite: String,
+    pub score: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power_up: Option<PowerUp>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SegmentDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub difficulty: u32,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    pub obstacles: Vec<ObstacleDefinition>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ObstacleDefinition {
+    Barrier {
+        image: String,
+        x: Fixed,
+        y: Fixed,
+        #[serde(default, skip_serializing_if = "is_false")]
+        rolling: bool,
+    },
+    Platform {
+        platform: String,
+        x: Fixed,
+        y: Fixed,
+        #[serde(default, skip_serializing_if = "PlatformMotion::is_still")]
+        motion: PlatformMotion,
+    },
+    // A platform's sprites and boxes hung up low enough that the boy has to
+    // slide under them.
+    Overhead {
+        platform: String,
+        x: Fixed,
+        y: Fixed,
+    },
+    Collectible {
+        collectible: String,
+        x: Fixed,
+        y: Fixed,
+    },
+    Enemy {
+        enemy: EnemyKind,
+        x: Fixed,
+        y: Fixed,
     },
-    Rect {
-        position: Point { x: 60, y: 0 },
-        width: 384 - (60 * 2),
-        height: 93,
+    // Ground left out from x to x + width. Only tile columns wholly inside
+    // it are removed, so the hole can come out up to a tile narrower.
+    Pit {
+        x: Fixed,
+        width: Fixed,
     },
-    Rect {
-        position: Point { x: 384 - 60, y: 0 },
-        width: 60,
-        height: 54,
+    // `count` collectibles spread evenly from (x, y) to (x + width, y),
+    // bulging up by `height` in the middle like a jump over whatever sits
+    // underneath.
+    CollectibleArc {
+        collectible: String,
+        x: Fixed,
+        y: Fixed,
+        count: u32,
+        width: Fixed,
+        height: Fixed,
     },
-];
+}
 
-pub fn stone_and_platform(
-    stone: HtmlImageElement,
-    sprite_sheet: Rc<SpriteSheet>,
-    offset_x: i16,
-) -> Vec<Box<dyn Obstacle>> {
-    vec![
-        Box::new(Barrier::new(Image::new(
-            stone,
-            Point {
-                x: offset_x + INITIAL_OBSTACLE_OFFSET,
-                y: STONE_ON_GROUND,
-            },
-        ))),
-        Box::new(create_floating_platform(
-            sprite_sheet,
+pub fn arc_positions(x: Fixed, y: Fixed, count: u32, width: Fixed, height: Fixed) -> Vec<Point> {
+    let steps = count.max(2) as i32 - 1;
+    (0..count as i32)
+        .map(|step| {
+            let along = Fixed::from_int(step) / Fixed::from_int(steps);
             Point {
-                x: offset_x + FIRST_PLATFORM,
-                y: LOW_PLATFORM,
-            },
-        )),
-    ]
+                x: x + width * along,
+                y: y - height * along * (Fixed::from_int(1) - along) * 4,
+            }
+        })
+        .collect()
 }
 
-pub fn platform_and_stone(
-    stone: HtmlImageElement,
-    sprite_sheet: Rc<SpriteSheet>,
-    offset_x: i16,
-) -> Vec<Box<dyn Obstacle>> {
-    vec![
-        Box::new(create_floating_platform(
-            sprite_sheet,
-            Point {
-                x: offset_x + INITIAL_OBSTACLE_OFFSET,
-                y: HIGH_PLATFORM,
-            },
-        )),
-        Box::new(Barrier::new(Image::new(
-            stone,
-            Point {
-                x: offset_x + FIRST_PLATFORM,
-                y: STONE_ON_GROUND,
-            },
-        ))),
-    ]
+struct PlatformTemplate {
+    sprites: Vec<String>,
+    bounding_boxes: Vec<Rect>,
+}
+
+enum ObstacleTemplate {
+    Barrier {
+        image: String,
+        bounding_box: Rect,
+        rolling: bool,
+    },
+    Platform {
+        template: Rc<PlatformTemplate>,
+        position: Point,
+        motion: PlatformMotion,
+    },
+    Overhead {
+        template: Rc<PlatformTemplate>,
+        position: Point,
+    },
+    Collectible {
+        template: Rc<CollectibleDefinition>,
+        position: Point,
+    },
+    Enemy {
+        kind: EnemyKind,
+        position: Point,
+    },
+    Pit(Pit),
+}
+
+struct Segment {
+    obstacles: Vec<ObstacleTemplate>,
 }
 
-pub fn stone_on_low_platform(
-    stone: HtmlImageElement,
+// The validated geometry of every segment, kept apart from textures and
+// sprites so it can be placed and checked for reachability without a browser.
+pub struct SegmentLayouts {
+    segments: Vec<Segment>,
+    names: Vec<String>,
+    ratings: Vec<SegmentRating>,
+    start: usize,
+    collectibles: HashMap<String, Rc<CollectibleDefinition>>,
+    power_ups: Vec<String>,
+}
+impl SegmentLayouts {
+    // Resolves every image, platform and sprite a segment refers to up front,
+    // so a typo in the data fails at startup with every problem listed
+    // instead of spawning a broken obstacle mid-run.
+    pub fn new(
+        definition: SegmentLibraryDefinition,
+        image_size: impl Fn(&str) -> Option<(Fixed, Fixed)>,
+        has_sprite: impl Fn(&str) -> bool,
+    ) -> Result<Self> {
+        let mut problems = Vec::new();
+
+        let mut platforms = HashMap::new();
+        for (name, platform) in definition.platforms {
+            for sprite in &platform.sprites {
+                if !has_sprite(sprite) {
+                    problems.push(format!("platform '{}': unknown sprite '{}'", name, sprite));
+                }
+            }
+            if platform.bounding_boxes.is_empty() {
+                problems.push(format!("platform '{}': no bounding boxes", name));
+            }
+            for bounding_box in &platform.bounding_boxes {
+                if bounding_box.width <= Fixed::ZERO || bounding_box.height <= Fixed::ZERO {
+                    problems.push(format!("platform '{}': empty bounding box", name));
+                }
+            }
+            platforms.insert(
+                name,
+                Rc::new(PlatformTemplate {
+                    sprites: platform.sprites,
+                    bounding_boxes: platform.bounding_boxes,
+                }),
+            );
+        }
+
+        let mut collectibles = HashMap::new();
+        for (name, collectible) in definition.collectibles {
+            if !has_sprite(&collectible.sprite) {
+                problems.push(format!(
+                    "collectible '{}': unknown sprite '{}'",
+                    name, collectible.sprite
+                ));
+            }
+            collectibles.insert(name, Rc::new(collectible));
+        }
+
+        let mut segments = Vec::new();
+        let mut names = Vec::new();
+        let mut ratings = Vec::new();
+        let mut start = None;
+        for segment in definition.segments {
+            if segment.name == definition.start {
+                start = Some(segments.len());
+            }
+            if segment.obstacles.is_empty() {
+                problems.push(format!("segment '{}': no obstacles", segment.name));
+            }
+            let mut obstacles = Vec::new();
+            for obstacle in segment.obstacles {
+                match obstacle {
+                    ObstacleDefinition::Barrier {
+                        image,
+                        x,
+                        y,
+                        rolling,
+                    } => {
+                        // Kept inside its own segment, a rolling barrier never
+                        // reaches obstacles the reachability checker has
+                        // already moved past.
+                        if rolling && x < ROLL_LIMIT {
+                            problems.push(format!(
+                                "segment '{}': rolling '{}' at x {} could roll out of the segment",
+                                segment.name,
+                                image,
+                                x.to_pixels()
+                            ));
+                        }
+                        match image_size(&image) {
+                            Some((width, height)) => obstacles.push(ObstacleTemplate::Barrier {
+                                image,
+                                bounding_box: Rect::new_from_x_y(x, y, width, height),
+                                rolling,
+                            }),
+                            None => problems.push(format!(
+                                "segment '{}': unknown image '{}'",
+                                segment.name, image
+                            )),
+                        }
+                    }
+                    ObstacleDefinition::Platform {
+                        platform,
+                        x,
+                        y,
+                        motion,
+                    } => {
+                        if let PlatformMotion::Bob { height, period } = motion {
+                            if period <= Fixed::ZERO || height < Fixed::ZERO {
+                                problems.push(format!(
+                                    "segment '{}': platform '{}' bobs with a bad height or period",
+                                    segment.name, platform
+                                ));
+                            }
+                        }
+                        match platforms.get(&platform) {
+                            Some(template) => obstacles.push(ObstacleTemplate::Platform {
+                                template: template.clone(),
+                                position: Point { x, y },
+                                motion,
+                            }),
+                            None => problems.push(format!(
+                                "segment '{}': unknown platform '{}'",
+                                segment.name, platform
+                            )),
+                        }
+                    }
+                    ObstacleDefinition::Overhead { platform, x, y } => {
+                        match platforms.get(&platform) {
+                            Some(template) => obstacles.push(ObstacleTemplate::Overhead {
+                                template: template.clone(),
+                                position: Point { x, y },
+                            }),
+                            None => problems.push(format!(
+                                "segment '{}': unknown platform '{}'",
+                                segment.name, platform
+                            )),
+                        }
+                    }
+                    ObstacleDefinition::Collectible { collectible, x, y } => {
+                        match collectibles.get(&collectible) {
+                            Some(template) => obstacles.push(ObstacleTemplate::Collectible {
+                                template: template.clone(),
+                                position: Point { x, y },
+                            }),
+                            None => problems.push(format!(
+                                "segment '{}': unknown collectible '{}'",
+                                segment.name, collectible
+                            )),
+                        }
+                    }
+                    ObstacleDefinition::Enemy { enemy, x, y } => {
+                        // Like rolling barriers, enemies must not chase out
+                        // of their own segment.
+                        if x < enemy.reach() {
+                            problems.push(format!(
+                                "segment '{}': {:?} at x {} could leave the segment",
+                                segment.name,
+                                enemy,
+                                x.to_pixels()
+                            ));
+                        }
+                        obstacles.push(ObstacleTemplate::Enemy {
+                            kind: enemy,
+                            position: Point { x, y },
+                        });
+                    }
+                    ObstacleDefinition::Pit { x, width } => {
+                        if width < PIT_MIN_WIDTH {
+                            problems.push(format!(
+                                "segment '{}': pit at x {} is narrower than {}",
+                                segment.name,
+                                x.to_pixels(),
+                                PIT_MIN_WIDTH.to_pixels()
+                            ));
+                        }
+                        obstacles.push(ObstacleTemplate::Pit(Pit {
+                            left: x,
+                            right: x + width,
+                        }));
+                    }
+                    ObstacleDefinition::CollectibleArc {
+                        collectible,
+                        x,
+                        y,
+                        count,
+                        width,
+                        height,
+                    } => match collectibles.get(&collectible) {
+                        Some(template) => obstacles.extend(
+                            arc_positions(x, y, count, width, height)
+                                .into_iter()
+                                .map(|position| ObstacleTemplate::Collectible {
+                                    template: template.clone(),
+                                    position,
+                                }),
+                        ),
+                        None => problems.push(format!(
+                            "segment '{}': unknown collectible '{}'",
+                            segment.name, collectible
+                        )),
+                    },
+                }
+            }
+            segments.push(Segment { obstacles });
+            names.push(segment.name);
+            ratings.push(SegmentRating {
+                difficulty: segment.difficulty,
+                weight: segment.weight,
+            });
+        }
+
+        if segments.is_empty() {
+            problems.push("no segments defined".to_string());
+        }
+        if start.is_none() {
+            problems.push(format!("unknown start segment '{}'", definition.start));
+        }
+
+        // Sorted so the same seed spawns the same power-ups on every run.
+        let mut power_ups: Vec<String> = collectibles
+            .iter()
+            .filter(|(_, collectible)| collectible.power_up.is_some())
+            .map(|(name, _)| name.clone())
+            .collect();
+        power_ups.sort();
+
+        match start {
+            Some(start) if problems.is_empty() => Ok(SegmentLayouts {
+                segments,
+                names,
+                ratings,
+                start,
+                collectibles,
+                power_ups,
+            }),
+            _ => Err(anyhow!("Invalid segments:\n  {}", problems.join("\n  "))),
+        }
+    }
+
+    pub fn ratings(&self) -> &[SegmentRating] {
+        &self.ratings
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|segment| segment == name)
+    }
+
+    // Collectibles that grant a power-up, which segment generation drops in
+    // on its own rather than through any one segment.
+    pub fn power_ups(&self) -> &[String] {
+        &self.power_ups
+    }
+
+    // Only what can block the boy; collectibles never change whether a
+    // segment can be cleared. A crumbling platform only holds for a moment,
+    // so the checker plays safe and has the boy stay clear of it altogether.
+    pub fn colliders(&self, index: usize, offset_x: Fixed) -> Vec<Collider> {
+        let mut colliders = Vec::new();
+        for obstacle in &self.segments[index].obstacles {
+            match obstacle {
+                ObstacleTemplate::Barrier {
+                    bounding_box,
+                    rolling,
+                    ..
+                } => {
+                    let mut bounding_box = *bounding_box;
+                    bounding_box.set_x(offset_x + bounding_box.x());
+                    colliders.push(if *rolling {
+                        Collider::RollingBarrier(bounding_box)
+                    } else {
+                        Collider::Barrier(bounding_box)
+                    });
+                }
+                ObstacleTemplate::Platform {
+                    template,
+                    position,
+                    motion,
+                } => {
+                    let bounding_boxes = template.bounding_boxes.iter().map(|bounding_box| {
+                        Rect::new_from_x_y(
+                            offset_x + position.x + bounding_box.x(),
+                            position.y + bounding_box.y(),
+                            bounding_box.width,
+                            bounding_box.height,
+                        )
+                    });
+                    match motion {
+                        PlatformMotion::Crumble => {
+                            colliders.extend(bounding_boxes.map(Collider::Barrier))
+                        }
+                        PlatformMotion::Still => colliders.push(Collider::Platform {
+                            bounding_boxes: bounding_boxes.collect(),
+                            bob: None,
+                        }),
+                        PlatformMotion::Bob { height, period } => {
+                            colliders.push(Collider::Platform {
+                                bounding_boxes: bounding_boxes.collect(),
+                                bob: Some((*height, *period)),
+                            })
+                        }
+                    }
+                }
+                ObstacleTemplate::Overhead { template, position } => {
+                    colliders.extend(template.bounding_boxes.iter().map(|bounding_box| {
+                        Collider::Overhead(Rect::new_from_x_y(
+                            offset_x + position.x + bounding_box.x(),
+                            position.y + bounding_box.y(),
+                            bounding_box.width,
+                            bounding_box.height,
+                        ))
+                    }))
+                }
+                ObstacleTemplate::Enemy { kind, position } => colliders.push(Collider::Enemy {
+                    kind: *kind,
+                    home: Point {
+                        x: offset_x + position.x,
+                        y: position.y,
+                    },
+                }),
+                ObstacleTemplate::Pit(pit) => colliders.push(Collider::Pit(Pit {
+                    left: offset_x + pit.left,
+                    right: offset_x + pit.right,
+                })),
+                ObstacleTemplate::Collectible { .. } => {}
+            }
+        }
+        colliders
+    }
+
+    pub fn pits(&self, index: usize, offset_x: Fixed) -> Vec<Pit> {
+        self.segments[index]
+            .obstacles
+            .iter()
+            .filter_map(|obstacle| match obstacle {
+                ObstacleTemplate::Pit(pit) => Some(Pit {
+                    left: offset_x + pit.left,
+                    right: offset_x + pit.right,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+pub struct SegmentLibrary {
+    layouts: SegmentLayouts,
+    textures: HashMap<String, Texture>,
     sprite_sheet: Rc<SpriteSheet>,
-    offset_x: i16,
-) -> Vec<Box<dyn Obstacle>> {
-    vec![
-        Box::new(create_floating_platform(
-            sprite_sheet,
-            Point {
-                x: offset_x + INITIAL_OBSTACLE_OFFSET,
-                y: LOW_PLATFORM,
-            },
-        )),
-        Box::new(Barrier::new(Image::new(
-            stone,
-            Point {
-                x: offset_x + INITIAL_OBSTACLE_OFFSET + 160,
-                y: STONE_ON_LOW_PLATFORM,
-            },
-        ))),
-    ]
+    enemy_sheet: Rc<SpriteSheet>,
 }
+impl SegmentLibrary {
+    // Built from the data asset `id`, which is blamed for any segment that
+    // doesn't hold together.
+    pub fn new(
+        assets: &Assets,
+        id: &str,
+        sprite_sheet: Rc<SpriteSheet>,
+    ) -> Result<Self, AssetError> {
+        let layouts = SegmentLayouts::new(
+            assets.data(id)?,
+            |image| {
+                assets
+                    .texture(image)
+                    .ok()
+                    .map(|texture| (texture.width().into(), texture.height().into()))
+            },
+            |sprite| sprite_sheet.cell(sprite).is_some(),
+        )
+        .map_err(|err| assets.error(AssetKind::Data, id, err))?;
+
+        let mut textures = HashMap::new();
+        for segment in &layouts.segments {
+            for obstacle in &segment.obstacles {
+                if let ObstacleTemplate::Barrier { image, .. } = obstacle {
+                    textures.insert(image.clone(), assets.texture(image)?);
+                }
+            }
+        }
+
+        Ok(SegmentLibrary {
+            layouts,
+            textures,
+            sprite_sheet,
+            enemy_sheet: Rc::new(SpriteSheet::new(
+                assets.sheet("enemies")?,
+                assets.texture("enemies")?,
+            )),
+        })
+    }
 
-fn create_floating_platform(sprite_sheet: Rc<SpriteSheet>, position: Point) -> Platform {
-    Platform::new(
-        sprite_sheet,
-        position,
-        &FLOATING_PLATFORM_SPRITES,
-        &FLOATING_PLATFORM_BOUNDING_BOXES,
-    )
+    pub fn layouts(&self) -> &SegmentLayouts {
+        &self.layouts
+    }
+
+    // Spawns a segment's obstacles into the world and digs its pits out of
+    // the ground ahead, returning where the segment ends.
+    pub fn spawn(
+        &self,
+        index: usize,
+        offset_x: Fixed,
+        world: &mut GameWorld,
+        ground: &mut Ground,
+    ) -> Fixed {
+        let obstacles: Vec<Entity> = self.layouts.segments[index]
+            .obstacles
+            .iter()
+            .filter_map(|obstacle| match obstacle {
+                ObstacleTemplate::Barrier {
+                    image,
+                    bounding_box,
+                    rolling,
+                } => Some(spawn_barrier(
+                    world,
+                    &self.textures[image],
+                    Point {
+                        x: offset_x + bounding_box.x(),
+                        y: bounding_box.y(),
+                    },
+                    *rolling,
+                )),
+                ObstacleTemplate::Platform {
+                    template,
+                    position,
+                    motion,
+                } => Some(spawn_platform(
+                    world,
+                    &self.sprite_sheet,
+                    Point {
+                        x: offset_x + position.x,
+                        y: position.y,
+                    },
+                    &template.sprites,
+                    &template.bounding_boxes,
+                    *motion,
+                )),
+                ObstacleTemplate::Overhead { template, position } => Some(spawn_overhead(
+                    world,
+                    &self.sprite_sheet,
+                    Point {
+                        x: offset_x + position.x,
+                        y: position.y,
+                    },
+                    &template.sprites,
+                    &template.bounding_boxes,
+                )),
+                ObstacleTemplate::Enemy { kind, position } => Some(spawn_enemy(
+                    world,
+                    &self.enemy_sheet,
+                    *kind,
+                    Point {
+                        x: offset_x + position.x,
+                        y: position.y,
+                    },
+                )),
+                ObstacleTemplate::Collectible { template, position } => Some(self.collectible(
+                    world,
+                    template,
+                    Point {
+                        x: offset_x + position.x,
+                        y: position.y,
+                    },
+                )),
+                ObstacleTemplate::Pit(_) => None,
+            })
+            .collect();
+        let pits = self.layouts.pits(index, offset_x);
+        let end = pits
+            .iter()
+            .map(|pit| pit.right)
+            .fold(world.rightmost(&obstacles), Fixed::max);
+        ground.dig(pits);
+        end
+    }
+
+    pub fn spawn_collectible(
+        &self,
+        name: &str,
+        position: Point,
+        world: &mut GameWorld,
+    ) -> Option<Entity> {
+        self.layouts
+            .collectibles
+            .get(name)
+            .map(|template| self.collectible(world, template, position))
+    }
+
+    fn collectible(
+        &self,
+        world: &mut GameWorld,
+        template: &CollectibleDefinition,
+        position: Point,
+    ) -> Entity {
+        spawn_collectible(
+            world,
+            &self.sprite_sheet,
+            position,
+            &template.sprite,
+            Pickup {
+                score: template.score,
+                power_up: template.power_up,
+            },
+        )
+    }
 }
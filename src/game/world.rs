@@ -0,0 +1,236 @@
+use super::enemies::{enemy_contact, Enemy, EnemyContact};
+use super::obstacles::{
+    barrier_contact, is_beneath, platform_contact, Contact, MovingPlatform, Pickup, RollingBarrier,
+};
+use super::rhb::RedHatBoy;
+use crate::engine::{Collider, ColliderKind, Entity, Fixed, Point, Rect, Sprite, World};
+
+// Landing lifts the boy out of whatever he sank into this tick, never by
+// more than he fell, so anything he can touch once an earlier contact has
+// moved him is still within this margin of where he started.
+const CONTACT_MARGIN: Fixed = Fixed::from_int(32);
+
+// What an entity does each tick beyond sitting where it was spawned. Still
+// platforms and barriers have no behaviour at all; they are just a position,
+// a sprite and a collider.
+#[derive(Hash)]
+pub enum Behaviour {
+    Player(RedHatBoy),
+    Platform(MovingPlatform),
+    Rolling(RollingBarrier),
+    Pickup(Pickup),
+    Enemy(Enemy),
+}
+impl Behaviour {
+    fn update(&mut self, entity: Entity, world: &mut GameWorld, boy: &RedHatBoy) {
+        match self {
+            Behaviour::Player(_) => {}
+            Behaviour::Platform(platform) => platform.update(entity, world, boy.position().x),
+            Behaviour::Rolling(rolling) => rolling.update(entity, world, boy.position().x),
+            Behaviour::Pickup(pickup) => pickup.update(entity, world, boy),
+            Behaviour::Enemy(enemy) => enemy.update(entity, world, boy.position().x),
+        }
+    }
+}
+
+pub type GameWorld = World<Behaviour>;
+
+// The boy's state machine keeps his own position and velocity, since every
+// transition depends on them. Whenever a system is done with him they are
+// copied out to his components, so he is drawn and measured like anything
+// else in the world.
+impl World<Behaviour> {
+    pub fn spawn_player(&mut self, boy: RedHatBoy) -> Entity {
+        let entity = self.spawn(boy.position());
+        self.sprites.insert(entity, boy.sprite());
+        self.put_player(entity, boy);
+        entity
+    }
+
+    pub fn player(&self, entity: Entity) -> Option<&RedHatBoy> {
+        match self.behaviours.get(&entity) {
+            Some(Behaviour::Player(boy)) => Some(boy),
+            _ => None,
+        }
+    }
+
+    pub fn player_mut(&mut self, entity: Entity) -> Option<&mut RedHatBoy> {
+        match self.behaviours.get_mut(&entity) {
+            Some(Behaviour::Player(boy)) => Some(boy),
+            _ => None,
+        }
+    }
+
+    fn take_player(&mut self, entity: Entity) -> Option<RedHatBoy> {
+        match self.behaviours.remove(&entity) {
+            Some(Behaviour::Player(boy)) => Some(boy),
+            Some(behaviour) => {
+                self.behaviours.insert(entity, behaviour);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn put_player(&mut self, entity: Entity, boy: RedHatBoy) {
+        self.set_position(entity, boy.position());
+        if let Some(Sprite::Frame { name, .. }) = self.sprites.get_mut(&entity) {
+            *name = boy.frame_name();
+        }
+        self.set_collider(
+            entity,
+            Collider {
+                kind: ColliderKind::Sensor,
+                bounding_boxes: boy.hitboxes(),
+            },
+        );
+        self.behaviours.insert(entity, Behaviour::Player(boy));
+    }
+
+    // Lets everything else react to where the boy has got to. Each behaviour
+    // is taken out of the world while it runs so it can change any component,
+    // including despawning its own entity.
+    pub fn update_behaviours(&mut self, player: Entity) {
+        let boy = match self.take_player(player) {
+            Some(boy) => boy,
+            None => return,
+        };
+        let entities: Vec<Entity> = self.behaviours.keys().copied().collect();
+        for entity in entities {
+            if let Some(mut behaviour) = self.behaviours.remove(&entity) {
+                behaviour.update(entity, self, &boy);
+                if self.contains(entity) {
+                    self.behaviours.insert(entity, behaviour);
+                }
+            }
+        }
+        self.put_player(player, boy);
+    }
+
+    // Checks the boy against every collider near where he moved this tick,
+    // having started it at `from`: the ground first, so he is standing on it
+    // before anything else sees where he is, then the rest in the order they
+    // were spawned. The reachability checker replays them in the same order.
+    // Pickups he touches are removed and handed back.
+    pub fn resolve_contacts(&mut self, player: Entity, from: Point) -> Vec<Pickup> {
+        let mut pickups = Vec::new();
+        let mut boy = match self.take_player(player) {
+            Some(boy) => boy,
+            None => return pickups,
+        };
+        let bounding_box = Rect::enclosing(&boy.bounding_boxes()).unwrap_or_default();
+        let motion = motion_since(&boy, from);
+        let nearby = self.colliders_near(&Rect::new_from_x_y(
+            bounding_box.x() - motion.x.max(Fixed::ZERO) - CONTACT_MARGIN,
+            bounding_box.y() - motion.y.max(Fixed::ZERO) - CONTACT_MARGIN,
+            bounding_box.width + motion.x.abs() + CONTACT_MARGIN * 2,
+            bounding_box.height + motion.y.abs() + CONTACT_MARGIN * 2,
+        ));
+        let (ground, others): (Vec<Entity>, Vec<Entity>) = nearby
+            .into_iter()
+            .filter(|entity| *entity != player)
+            .partition(|entity| {
+                matches!(
+                    self.collider(*entity),
+                    Some(Collider {
+                        kind: ColliderKind::Ground,
+                        ..
+                    })
+                )
+            });
+        for entity in ground.into_iter().chain(others) {
+            let kind = match self.collider(entity) {
+                Some(collider) => collider.kind,
+                None => continue,
+            };
+            let bounding_boxes = self.bounding_boxes(entity);
+            match kind {
+                ColliderKind::Solid | ColliderKind::Ground => match platform_contact(
+                    &boy.bounding_boxes(),
+                    motion_since(&boy, from),
+                    &bounding_boxes,
+                ) {
+                    Contact::Land(position) => {
+                        boy.land_on(position);
+                        if let Some(Behaviour::Platform(platform)) =
+                            self.behaviours.get_mut(&entity)
+                        {
+                            platform.landed_on();
+                        }
+                    }
+                    Contact::HitCeiling(depth) => boy.hit_ceiling(depth),
+                    Contact::KnockOut => boy.knock_out(),
+                    Contact::None => {}
+                },
+                ColliderKind::Hazard => {
+                    if bounding_boxes.iter().any(|bounding_box| {
+                        matches!(
+                            barrier_contact(
+                                &boy.bounding_boxes(),
+                                motion_since(&boy, from),
+                                bounding_box
+                            ),
+                            Contact::KnockOut
+                        )
+                    }) {
+                        boy.knock_out();
+                    }
+                }
+                ColliderKind::Overhead => {
+                    for bounding_box in &bounding_boxes {
+                        let motion = motion_since(&boy, from);
+                        if is_beneath(&boy.bounding_boxes(), motion, bounding_box) {
+                            boy.stay_down();
+                        }
+                        if let Contact::KnockOut =
+                            barrier_contact(&boy.bounding_boxes(), motion, bounding_box)
+                        {
+                            boy.knock_out();
+                        }
+                    }
+                }
+                ColliderKind::Sensor => match self.behaviours.get_mut(&entity) {
+                    Some(Behaviour::Pickup(pickup))
+                        if boy.bounding_boxes().iter().any(|hitbox| {
+                            bounding_boxes
+                                .iter()
+                                .any(|bounding_box| hitbox.intersects(bounding_box))
+                        }) =>
+                    {
+                        pickups.push(*pickup);
+                        self.despawn(entity);
+                    }
+                    Some(Behaviour::Enemy(enemy)) => {
+                        for bounding_box in &bounding_boxes {
+                            match enemy_contact(
+                                &boy.bounding_boxes(),
+                                motion_since(&boy, from),
+                                boy.is_sliding(),
+                                bounding_box,
+                            ) {
+                                EnemyContact::Stomp => {
+                                    boy.bounce();
+                                    enemy.stomp();
+                                }
+                                EnemyContact::Tumble => enemy.tumble(),
+                                EnemyContact::KnockOut => boy.knock_out(),
+                                EnemyContact::None => {}
+                            }
+                        }
+                    }
+                    _ => {}
+                },
+            }
+        }
+        self.put_player(player, boy);
+        pickups
+    }
+}
+
+// Landing moves him, so this is worked out afresh for every contact.
+fn motion_since(boy: &RedHatBoy, from: Point) -> Point {
+    Point {
+        x: boy.position().x - from.x,
+        y: boy.position().y - from.y,
+    }
+}
@@ -19,9 +19,9 @@ pub fn main_js() -> Result<(), JsValue> {
     browser::spawn_local(async move {
         let game = WalkTheDog::new();
 
-        GameLoop::start(game)
-            .await
-            .expect("Could not start game loop");
+        if let Err(err) = GameLoop::start(game).await {
+            log!("Could not start game loop {:#?}", err);
+        }
     });
 
     Ok(())
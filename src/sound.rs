@@ -8,6 +8,11 @@ pub fn create_audio_context() -> Result<AudioContext> {
     AudioContext::new().map_err(|err| anyhow!("Could not create audio context: {:#?}", err))
 }
 
+pub fn create_silent_buffer(ctx: &AudioContext) -> Result<AudioBuffer> {
+    ctx.create_buffer(1, 1, ctx.sample_rate())
+        .map_err(|err| anyhow!("Error creating silent buffer {:#?}", err))
+}
+
 fn create_buffer_source(ctx: &AudioContext) -> Result<AudioBufferSourceNode> {
     ctx.create_buffer_source()
         .map_err(|err| anyhow!("Error creating buffer source {:#?}", err))